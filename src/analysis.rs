@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::selector::*;
+use crate::types::*;
+
+/// The values a present key may take, either an explicit set or every
+/// value except an explicit set
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Values {
+    Only(BTreeSet<LabelValue>),
+    Except(BTreeSet<LabelValue>),
+}
+
+impl Values {
+    fn intersect(self, other: Values) -> Values {
+        match (self, other) {
+            (Values::Only(a), Values::Only(b)) => {
+                Values::Only(a.intersection(&b).cloned().collect())
+            }
+            (Values::Only(a), Values::Except(b)) | (Values::Except(b), Values::Only(a)) => {
+                Values::Only(a.difference(&b).cloned().collect())
+            }
+            (Values::Except(a), Values::Except(b)) => {
+                Values::Except(a.union(&b).cloned().collect())
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Values::Only(values) => values.is_empty(),
+            // there are always more valid label values than can be excluded
+            Values::Except(_) => false,
+        }
+    }
+
+    fn is_subset(&self, other: &Values) -> bool {
+        match (self, other) {
+            (Values::Only(a), Values::Only(b)) => a.is_subset(b),
+            (Values::Only(a), Values::Except(b)) => a.is_disjoint(b),
+            (Values::Except(_), Values::Only(_)) => false,
+            (Values::Except(a), Values::Except(b)) => b.is_subset(a),
+        }
+    }
+
+    /// Pick some value from the set
+    fn example(&self) -> Option<LabelValue> {
+        match self {
+            Values::Only(values) => values.iter().next().cloned(),
+            Values::Except(values) => std::iter::once(String::new())
+                .chain((0..).map(|i| format!("v{}", i)))
+                .map(LabelValue)
+                .find(|value| !values.contains(value)),
+        }
+    }
+}
+
+/// Everything the requirements of one selector say about a single key
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct KeyConstraint {
+    /// Whether the key may be missing from the labels
+    absent: bool,
+    /// The values the key may take when present
+    present: Values,
+}
+
+impl Default for KeyConstraint {
+    fn default() -> Self {
+        KeyConstraint {
+            absent: true,
+            present: Values::Except(BTreeSet::new()),
+        }
+    }
+}
+
+impl KeyConstraint {
+    fn restrict(self, requirement: &Requirement) -> KeyConstraint {
+        let (absent, present) = match requirement.operator {
+            Operator::Equals | Operator::DoubleEquals | Operator::In => {
+                (false, Values::Only(requirement.values.clone()))
+            }
+            Operator::NotEquals | Operator::NotIn => {
+                (true, Values::Except(requirement.values.clone()))
+            }
+            Operator::Exists => (false, Values::Except(BTreeSet::new())),
+            Operator::DoesNotExist => (true, Values::Only(BTreeSet::new())),
+        };
+        KeyConstraint {
+            absent: self.absent && absent,
+            present: self.present.intersect(present),
+        }
+    }
+
+    fn is_satisfiable(&self) -> bool {
+        self.absent || !self.present.is_empty()
+    }
+
+    fn is_subset(&self, other: &KeyConstraint) -> bool {
+        (!self.absent || other.absent) && self.present.is_subset(&other.present)
+    }
+
+    /// The fewest requirements expressing the constraint
+    fn requirements(&self, key: &Key) -> Vec<Requirement> {
+        let key = key.clone();
+        match (self.absent, &self.present) {
+            (true, Values::Only(values)) => {
+                debug_assert!(values.is_empty());
+                vec![Requirement::does_not_exist(key)]
+            }
+            (true, Values::Except(values)) => match values.len() {
+                0 => vec![],
+                1 => vec![Requirement::not_equals(key, first(values))],
+                _ => vec![Requirement::not_in_set(key, values.clone())],
+            },
+            (false, Values::Only(values)) => match values.len() {
+                1 => vec![Requirement::equals(key, first(values))],
+                _ => vec![Requirement::in_set(key, values.clone())],
+            },
+            (false, Values::Except(values)) => match values.len() {
+                0 => vec![Requirement::exists(key)],
+                1 => vec![
+                    Requirement::exists(key.clone()),
+                    Requirement::not_equals(key, first(values)),
+                ],
+                _ => vec![
+                    Requirement::exists(key.clone()),
+                    Requirement::not_in_set(key, values.clone()),
+                ],
+            },
+        }
+    }
+}
+
+fn first(values: &BTreeSet<LabelValue>) -> LabelValue {
+    values.iter().next().unwrap().clone()
+}
+
+fn constraints<'a, I>(requirements: I) -> BTreeMap<&'a Key, KeyConstraint>
+where
+    I: IntoIterator<Item = &'a Requirement>,
+{
+    let mut res: BTreeMap<&Key, KeyConstraint> = BTreeMap::new();
+    for req in requirements {
+        let constraint = res.remove(&req.key).unwrap_or_default();
+        res.insert(&req.key, constraint.restrict(req));
+    }
+    res
+}
+
+/// Build labels satisfying every constraint, if that is possible
+fn example(constraints: &BTreeMap<&Key, KeyConstraint>) -> Option<LabelMap> {
+    let mut labels = LabelMap::new();
+    for (key, constraint) in constraints {
+        if constraint.absent {
+            continue;
+        }
+        labels.insert((*key).clone(), constraint.present.example()?);
+    }
+    Some(labels)
+}
+
+impl LabelSelector {
+    /// Whether any set of labels can match the selector at all
+    pub fn is_satisfiable(&self) -> bool {
+        constraints(self.requirements())
+            .values()
+            .all(KeyConstraint::is_satisfiable)
+    }
+    /// Build a set of labels matched by the selector, if there is one
+    pub fn example(&self) -> Option<LabelMap> {
+        example(&constraints(self.requirements()))
+    }
+    /// The selector matching exactly the labels matched by both selectors
+    pub fn intersection(&self, other: &LabelSelector) -> LabelSelector {
+        self.requirements()
+            .iter()
+            .chain(other.requirements())
+            .cloned()
+            .collect()
+    }
+    /// Whether some set of labels is matched by both selectors
+    pub fn overlaps(&self, other: &LabelSelector) -> bool {
+        constraints(self.requirements().iter().chain(other.requirements()))
+            .values()
+            .all(KeyConstraint::is_satisfiable)
+    }
+    /// Build a set of labels matched by both selectors, if there is one
+    pub fn overlap_example(&self, other: &LabelSelector) -> Option<LabelMap> {
+        example(&constraints(
+            self.requirements().iter().chain(other.requirements()),
+        ))
+    }
+    /// Whether every set of labels matched by this selector is also matched
+    /// by `other`
+    pub fn is_subset_of(&self, other: &LabelSelector) -> bool {
+        let ours = constraints(self.requirements());
+        if !ours.values().all(KeyConstraint::is_satisfiable) {
+            return true;
+        }
+        let unconstrained = KeyConstraint::default();
+        constraints(other.requirements())
+            .iter()
+            .all(|(key, theirs)| ours.get(key).unwrap_or(&unconstrained).is_subset(theirs))
+    }
+    /// Whether both selectors match exactly the same sets of labels
+    pub fn is_equivalent(&self, other: &LabelSelector) -> bool {
+        self.is_subset_of(other) && other.is_subset_of(self)
+    }
+    /// Drop redundant requirements, e.g. `env in (a,b),env=a` becomes `env=a`
+    ///
+    /// Returns `None` for a contradictory selector that can never match.
+    pub fn simplify(&self) -> Option<LabelSelector> {
+        let constraints = constraints(self.requirements());
+        if !constraints.values().all(KeyConstraint::is_satisfiable) {
+            return None;
+        }
+        Some(
+            constraints
+                .iter()
+                .flat_map(|(key, constraint)| constraint.requirements(key))
+                .collect(),
+        )
+    }
+}
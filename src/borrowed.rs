@@ -0,0 +1,137 @@
+//! Keys, values and labels borrowing from the parsed input
+//!
+//! These are what the parsers produce before allocating, use them to scan
+//! many labels and only call `to_owned` on the ones worth keeping.
+
+use alloc::string::ToString;
+use core::fmt;
+
+use crate::parser;
+use crate::types::*;
+
+/// A `Key` borrowing its prefix and name from the input, ordered as `Key`
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord)]
+pub struct KeyRef<'a> {
+    prefix: Option<&'a str>,
+    name: &'a str,
+}
+
+impl<'a> KeyRef<'a> {
+    pub(crate) fn new(prefix: Option<&'a str>, name: &'a str) -> Self {
+        KeyRef { prefix, name }
+    }
+    pub fn parse_str(input: &'a str) -> Result<Self, Error> {
+        parser::label_key_ref_from_str(input)
+    }
+    pub fn parse_str_with_profile(input: &'a str, profile: Profile) -> Result<Self, Error> {
+        parser::label_key_ref_from_str_with_profile(input, profile)
+    }
+    pub fn prefix(&self) -> Option<&'a str> {
+        self.prefix
+    }
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+    pub fn has_prefix(&self) -> bool {
+        self.prefix.is_some()
+    }
+    pub fn to_owned(&self) -> Key {
+        Key::new(
+            self.prefix.map(|prefix| KeyPrefix(prefix.to_string())),
+            KeyName(self.name.to_string()),
+        )
+    }
+}
+
+impl<'a> fmt::Display for KeyRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(prefix) = self.prefix {
+            write!(f, "{}/", prefix)?;
+        }
+        write!(f, "{}", self.name)
+    }
+}
+
+impl<'a> PartialEq<Key> for KeyRef<'a> {
+    fn eq(&self, other: &Key) -> bool {
+        self.prefix == other.prefix() && self.name == other.name()
+    }
+}
+
+impl<'a> From<KeyRef<'a>> for Key {
+    fn from(key: KeyRef<'a>) -> Self {
+        key.to_owned()
+    }
+}
+
+/// A `LabelValue` borrowed from the input
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord)]
+pub struct LabelValueRef<'a>(pub(crate) &'a str);
+
+impl<'a> LabelValueRef<'a> {
+    pub fn parse_str(input: &'a str) -> Result<Self, Error> {
+        parser::label_value_ref_from_str(input)
+    }
+    pub fn parse_str_with_profile(input: &'a str, profile: Profile) -> Result<Self, Error> {
+        parser::label_value_ref_from_str_with_profile(input, profile)
+    }
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+    pub fn to_owned(&self) -> LabelValue {
+        LabelValue(self.0.to_string())
+    }
+}
+
+impl<'a> fmt::Display for LabelValueRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<'a> core::ops::Deref for LabelValueRef<'a> {
+    type Target = str;
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl<'a> AsRef<str> for LabelValueRef<'a> {
+    fn as_ref(&self) -> &str {
+        self.0
+    }
+}
+
+impl<'a> PartialEq<LabelValue> for LabelValueRef<'a> {
+    fn eq(&self, other: &LabelValue) -> bool {
+        self.0 == other.as_str()
+    }
+}
+
+impl<'a> From<LabelValueRef<'a>> for LabelValue {
+    fn from(value: LabelValueRef<'a>) -> Self {
+        value.to_owned()
+    }
+}
+
+/// A `Label` borrowed from the input
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord)]
+pub struct LabelRef<'a> {
+    pub key: KeyRef<'a>,
+    pub value: LabelValueRef<'a>,
+}
+
+impl<'a> LabelRef<'a> {
+    pub fn new(key: KeyRef<'a>, value: LabelValueRef<'a>) -> Self {
+        LabelRef { key, value }
+    }
+    pub fn to_owned(&self) -> Label {
+        Label::new(self.key.to_owned(), self.value.to_owned())
+    }
+}
+
+impl<'a> From<LabelRef<'a>> for Label {
+    fn from(label: LabelRef<'a>) -> Self {
+        label.to_owned()
+    }
+}
@@ -0,0 +1,406 @@
+//! Store arbitrary data in label values and get it back
+//!
+//! Typed values are stored with the codecs implementing `LabelValueCodec`.
+//!
+//! `LabelValue::escape` maps any string to a label value that
+//! `LabelValue::unescape` turns back into exactly the same string. Strings
+//! that already are valid values without a `_` are kept as they are.
+//! Otherwise the value is `x` followed by the string with every byte that is
+//! not allowed written as `_` and two uppercase hex digits, e.g.
+//! `dev@example.com` becomes `xdev_40example.com`. Leading and trailing `-`
+//! and `.` are escaped too, and so is `_` itself, so a value holds escapes if
+//! and only if it contains a `_`.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use crate::parser::label_value_from_str_with_profile;
+use crate::types::*;
+
+#[cfg(feature = "std")]
+pub use self::timestamp::TimestampCodec;
+
+const MAX_VALUE_LEN: usize = 63;
+const ESCAPE: u8 = b'_';
+const MARKER: u8 = b'x';
+const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+fn is_literal(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'.'
+}
+
+fn needs_escaping(input: &str) -> bool {
+    let bytes = input.as_bytes();
+    match (bytes.first(), bytes.last()) {
+        (Some(first), Some(last)) => {
+            !first.is_ascii_alphanumeric()
+                || !last.is_ascii_alphanumeric()
+                || !bytes.iter().all(|&b| is_literal(b))
+        }
+        _ => false,
+    }
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    HEX.iter().position(|&h| h == b).map(|i| i as u8)
+}
+
+impl LabelValue {
+    /// Encode any string as a label value, reversibly
+    ///
+    /// Fails with `Error::ValueTooLong` if the encoded value has more than 63
+    /// characters.
+    pub fn escape(input: &str) -> Result<LabelValue, Error> {
+        let res = if needs_escaping(input) {
+            let bytes = input.as_bytes();
+            let mut res = String::with_capacity(input.len() + 1);
+            res.push(MARKER as char);
+            for (i, &b) in bytes.iter().enumerate() {
+                // escaped at both ends, so an escaped value always holds a `_`
+                let at_end = i == 0 || i == bytes.len() - 1;
+                let literal = if at_end {
+                    b.is_ascii_alphanumeric()
+                } else {
+                    is_literal(b)
+                };
+                if literal {
+                    res.push(b as char);
+                } else {
+                    res.push(ESCAPE as char);
+                    res.push(HEX[usize::from(b >> 4)] as char);
+                    res.push(HEX[usize::from(b & 0xf)] as char);
+                }
+            }
+            res
+        } else {
+            input.to_string()
+        };
+        if res.len() > MAX_VALUE_LEN {
+            return Err(Error::ValueTooLong {
+                len: res.len(),
+                offset: 0,
+            });
+        }
+        Ok(LabelValue(res))
+    }
+
+    /// Decode a value made by `LabelValue::escape`
+    ///
+    /// Fails with `Error::InvalidEscape` if the value holds escapes but was
+    /// not made by `LabelValue::escape`.
+    pub fn unescape(&self) -> Result<String, Error> {
+        let bytes = self.0.as_bytes();
+        if !bytes.contains(&ESCAPE) {
+            return Ok(self.0.clone());
+        }
+        if bytes[0] != MARKER {
+            return Err(Error::InvalidEscape { offset: 0 });
+        }
+        let mut res = Vec::with_capacity(bytes.len());
+        // where each decoded byte starts in the value, to report bad UTF-8
+        let mut offsets = Vec::with_capacity(bytes.len());
+        let mut i = 1;
+        while i < bytes.len() {
+            offsets.push(i);
+            if bytes[i] == ESCAPE {
+                let hi = bytes.get(i + 1).and_then(|&b| hex_digit(b));
+                let lo = bytes.get(i + 2).and_then(|&b| hex_digit(b));
+                match (hi, lo) {
+                    (Some(hi), Some(lo)) => res.push(hi << 4 | lo),
+                    _ => return Err(Error::InvalidEscape { offset: i }),
+                }
+                i += 3;
+            } else {
+                res.push(bytes[i]);
+                i += 1;
+            }
+        }
+        String::from_utf8(res).map_err(|err| Error::InvalidEscape {
+            offset: offsets[err.utf8_error().valid_up_to()],
+        })
+    }
+}
+
+/// Converts typed values to and from label values
+///
+/// Every value `encode` accepts is decoded back to an equal value.
+pub trait LabelValueCodec {
+    type Value;
+    fn encode(&self, value: &Self::Value) -> Result<LabelValue, Error>;
+    fn decode(&self, value: &LabelValue) -> Result<Self::Value, Error>;
+}
+
+/// Any string, with `LabelValue::escape`
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct EscapedCodec;
+
+impl LabelValueCodec for EscapedCodec {
+    type Value = String;
+    fn encode(&self, value: &String) -> Result<LabelValue, Error> {
+        LabelValue::escape(value)
+    }
+    fn decode(&self, value: &LabelValue) -> Result<String, Error> {
+        value.unescape()
+    }
+}
+
+/// `true` or `false`
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct BoolCodec;
+
+impl LabelValueCodec for BoolCodec {
+    type Value = bool;
+    fn encode(&self, value: &bool) -> Result<LabelValue, Error> {
+        Ok(LabelValue(value.to_string()))
+    }
+    fn decode(&self, value: &LabelValue) -> Result<bool, Error> {
+        match value.as_str() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(Error::InvalidEncoding("boolean")),
+        }
+    }
+}
+
+/// Signed integers in decimal, with an `n` in place of the minus sign as
+/// values must start with an alphanumeric character, e.g. `n42` for -42
+///
+/// Only the canonical form is decoded: no leading zeros and no `n0`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct IntegerCodec;
+
+impl LabelValueCodec for IntegerCodec {
+    type Value = i64;
+    fn encode(&self, value: &i64) -> Result<LabelValue, Error> {
+        Ok(LabelValue(if *value < 0 {
+            format!("n{}", value.unsigned_abs())
+        } else {
+            value.to_string()
+        }))
+    }
+    fn decode(&self, value: &LabelValue) -> Result<i64, Error> {
+        let err = Error::InvalidEncoding("integer");
+        let (negative, digits) = match value.strip_prefix('n') {
+            Some(digits) => (true, digits),
+            None => (false, value.as_str()),
+        };
+        let canonical = digits.bytes().all(|b| b.is_ascii_digit())
+            && (digits == "0" && !negative || !digits.is_empty() && !digits.starts_with('0'));
+        if !canonical {
+            return Err(err);
+        }
+        let magnitude: u64 = digits.parse().map_err(|_| err.clone())?;
+        if negative {
+            0i64.checked_sub_unsigned(magnitude).ok_or(err)
+        } else {
+            i64::try_from(magnitude).map_err(|_| err)
+        }
+    }
+}
+
+fn is_semver_identifier(id: &str) -> bool {
+    !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+fn is_semver_number(id: &str) -> bool {
+    !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()) && (id == "0" || !id.starts_with('0'))
+}
+
+/// Check `version` against the semver 2.0.0 grammar
+fn is_semver(version: &str) -> bool {
+    let (version, build) = match version.split_once('+') {
+        Some((version, build)) => (version, Some(build)),
+        None => (version, None),
+    };
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (version, None),
+    };
+    let core: Vec<_> = core.split('.').collect();
+    core.len() == 3
+        && core.iter().all(|id| is_semver_number(id))
+        && pre.iter().flat_map(|pre| pre.split('.')).all(|id| {
+            is_semver_identifier(id)
+                && (!id.bytes().all(|b| b.is_ascii_digit()) || is_semver_number(id))
+        })
+        && build
+            .iter()
+            .flat_map(|build| build.split('.'))
+            .all(is_semver_identifier)
+}
+
+/// Semver versions, with the `+` before build metadata written as `_`,
+/// e.g. `1.2.3-rc.1_build.5`
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct SemverCodec;
+
+impl LabelValueCodec for SemverCodec {
+    type Value = String;
+    fn encode(&self, value: &String) -> Result<LabelValue, Error> {
+        if !is_semver(value) {
+            return Err(Error::InvalidEncoding("semver version"));
+        }
+        label_value_from_str_with_profile(&value.replace('+', "_"), Profile::Strict)
+    }
+    fn decode(&self, value: &LabelValue) -> Result<String, Error> {
+        let version = value.replace('_', "+");
+        if !is_semver(&version) {
+            return Err(Error::InvalidEncoding("semver version"));
+        }
+        Ok(version)
+    }
+}
+
+#[cfg(feature = "std")]
+mod timestamp {
+    use alloc::format;
+    use core::convert::TryFrom;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::LabelValueCodec;
+    use crate::types::*;
+
+    const SECS_PER_DAY: i64 = 86_400;
+    /// Days from 0000-03-01 to 1970-01-01
+    const EPOCH_DAYS: i64 = 719_468;
+    const DAYS_PER_ERA: i64 = 146_097;
+
+    /// Days since the epoch of a proleptic gregorian date
+    fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * DAYS_PER_ERA + day_of_era - EPOCH_DAYS
+    }
+
+    /// The proleptic gregorian date of a number of days since the epoch
+    fn civil_from_days(days: i64) -> (i64, i64, i64) {
+        let days = days + EPOCH_DAYS;
+        let era = days.div_euclid(DAYS_PER_ERA);
+        let day_of_era = days.rem_euclid(DAYS_PER_ERA);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day)
+    }
+
+    fn days_in_month(year: i64, month: i64) -> i64 {
+        match month {
+            2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    fn parse_digits(digits: &str) -> Option<i64> {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    }
+
+    /// UTC timestamps in RFC 3339 with `-` in place of the colons, e.g.
+    /// `2021-06-30T14-05-09Z` or `2021-06-30T14-05-09.25Z`
+    ///
+    /// Years must be within 0000 to 9999 and fractions of a second are kept
+    /// down to the nanosecond.
+    #[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+    pub struct TimestampCodec;
+
+    impl LabelValueCodec for TimestampCodec {
+        type Value = SystemTime;
+        fn encode(&self, value: &SystemTime) -> Result<LabelValue, Error> {
+            let err = Error::InvalidEncoding("timestamp");
+            let (secs, nanos) = match value.duration_since(UNIX_EPOCH) {
+                Ok(since) => (
+                    i64::try_from(since.as_secs()).map_err(|_| err.clone())?,
+                    since.subsec_nanos(),
+                ),
+                Err(before) => {
+                    let before = before.duration();
+                    let secs = i64::try_from(before.as_secs()).map_err(|_| err.clone())?;
+                    match before.subsec_nanos() {
+                        0 => (-secs, 0),
+                        nanos => (-secs - 1, 1_000_000_000 - nanos),
+                    }
+                }
+            };
+            let (year, month, day) = civil_from_days(secs.div_euclid(SECS_PER_DAY));
+            if !(0..=9999).contains(&year) {
+                return Err(err);
+            }
+            let time = secs.rem_euclid(SECS_PER_DAY);
+            let mut res = format!(
+                "{:04}-{:02}-{:02}T{:02}-{:02}-{:02}",
+                year,
+                month,
+                day,
+                time / 3600,
+                time / 60 % 60,
+                time % 60
+            );
+            if nanos > 0 {
+                let fraction = format!("{:09}", nanos);
+                res.push('.');
+                res.push_str(fraction.trim_end_matches('0'));
+            }
+            res.push('Z');
+            Ok(LabelValue(res))
+        }
+        fn decode(&self, value: &LabelValue) -> Result<SystemTime, Error> {
+            let err = || Error::InvalidEncoding("timestamp");
+            let value = value.strip_suffix('Z').ok_or_else(err)?;
+            if value.len() < 19 || !value.is_char_boundary(19) {
+                return Err(err());
+            }
+            let (datetime, fraction) = value.split_at(19);
+            let separators = [(4, b'-'), (7, b'-'), (10, b'T'), (13, b'-'), (16, b'-')];
+            if separators
+                .iter()
+                .any(|&(i, sep)| datetime.as_bytes()[i] != sep)
+            {
+                return Err(err());
+            }
+            let field = |range: std::ops::Range<usize>| {
+                datetime.get(range).and_then(parse_digits).ok_or_else(err)
+            };
+            let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+            let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+            if !(1..=12).contains(&month)
+                || !(1..=days_in_month(year, month)).contains(&day)
+                || hour > 23
+                || minute > 59
+                || second > 59
+            {
+                return Err(err());
+            }
+            let nanos = match fraction.strip_prefix('.') {
+                None if fraction.is_empty() => 0,
+                Some(digits) if digits.len() <= 9 => {
+                    parse_digits(digits).ok_or_else(err)? * 10i64.pow(9 - digits.len() as u32)
+                }
+                _ => return Err(err()),
+            };
+            let secs = days_from_civil(year, month, day) * SECS_PER_DAY
+                + hour * 3600
+                + minute * 60
+                + second;
+            let time = if secs >= 0 {
+                UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64))
+            } else {
+                UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+            };
+            time.and_then(|time| time.checked_add(Duration::from_nanos(nanos as u64)))
+                .ok_or_else(err)
+        }
+    }
+}
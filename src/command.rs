@@ -0,0 +1,400 @@
+//! The arguments of `kubectl label` and `kubectl annotate`
+//!
+//! As kubectl does, resources come first, then the changes: `key=value` sets
+//! a key and `key-` removes it. Of the flags only `--overwrite` and `--list`
+//! are understood. kubectl's other flags, like `-n` or `--dry-run=server`,
+//! are skipped along with their values, and unknown ones are rejected.
+
+use std::collections::HashMap;
+
+use crate::types::*;
+
+/// A single change to the labels or annotations of a resource
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub enum Operation<V> {
+    /// `key=value`
+    Set(Key, V),
+    /// `key-`
+    Remove(Key),
+}
+
+pub type LabelOperation = Operation<LabelValue>;
+pub type AnnotationOperation = Operation<String>;
+
+/// A parsed `kubectl label` or `kubectl annotate` command line, without the
+/// subcommand itself
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct Command<V> {
+    resources: Vec<String>,
+    operations: Vec<Operation<V>>,
+    overwrite: bool,
+    list: bool,
+}
+
+pub type LabelCommand = Command<LabelValue>;
+pub type AnnotateCommand = Command<String>;
+
+/// What a command changes, in kubectl's messages
+struct Kind {
+    name: &'static str,
+    with_article: &'static str,
+}
+
+const LABEL: Kind = Kind {
+    name: "label",
+    with_article: "a label",
+};
+
+const ANNOTATION: Kind = Kind {
+    name: "annotation",
+    with_article: "an annotation",
+};
+
+/// kubectl records the command causing a change here, and lets it be
+/// overwritten without `--overwrite`
+const CHANGE_CAUSE: &str = "kubernetes.io/change-cause";
+
+/// How a kubectl flag takes its value
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Takes {
+    /// None, or a boolean given as `--flag=false`
+    Bool,
+    /// Given as `--flag=value` or as the next argument
+    Value,
+    /// Only given as `--flag=value`, the flag having a default otherwise
+    OptionalValue,
+}
+
+struct Flag {
+    name: &'static str,
+    short: Option<char>,
+    takes: Takes,
+}
+
+const fn flag(name: &'static str, short: Option<char>, takes: Takes) -> Flag {
+    Flag { name, short, takes }
+}
+
+/// The flags of `kubectl label` and `kubectl annotate` along with kubectl's
+/// global flags, all but `--overwrite` and `--list` being skipped
+const FLAGS: &[Flag] = &[
+    flag("overwrite", None, Takes::Bool),
+    flag("list", None, Takes::Bool),
+    flag("all", None, Takes::Bool),
+    flag("all-namespaces", Some('A'), Takes::Bool),
+    flag("allow-missing-template-keys", None, Takes::Bool),
+    flag("dry-run", None, Takes::OptionalValue),
+    flag("field-manager", None, Takes::Value),
+    flag("field-selector", None, Takes::Value),
+    flag("filename", Some('f'), Takes::Value),
+    flag("kustomize", Some('k'), Takes::Value),
+    flag("local", None, Takes::Bool),
+    flag("output", Some('o'), Takes::Value),
+    flag("recursive", Some('R'), Takes::Bool),
+    flag("resource-version", None, Takes::Value),
+    flag("selector", Some('l'), Takes::Value),
+    flag("show-managed-fields", None, Takes::Bool),
+    flag("template", None, Takes::Value),
+    // global flags
+    flag("as", None, Takes::Value),
+    flag("as-group", None, Takes::Value),
+    flag("as-uid", None, Takes::Value),
+    flag("cache-dir", None, Takes::Value),
+    flag("certificate-authority", None, Takes::Value),
+    flag("client-certificate", None, Takes::Value),
+    flag("client-key", None, Takes::Value),
+    flag("cluster", None, Takes::Value),
+    flag("context", None, Takes::Value),
+    flag("disable-compression", None, Takes::Bool),
+    flag("insecure-skip-tls-verify", None, Takes::Bool),
+    flag("kubeconfig", None, Takes::Value),
+    flag("log-flush-frequency", None, Takes::Value),
+    flag("match-server-version", None, Takes::Bool),
+    flag("namespace", Some('n'), Takes::Value),
+    flag("password", None, Takes::Value),
+    flag("profile", None, Takes::Value),
+    flag("profile-output", None, Takes::Value),
+    flag("request-timeout", None, Takes::Value),
+    flag("server", Some('s'), Takes::Value),
+    flag("tls-server-name", None, Takes::Value),
+    flag("token", None, Takes::Value),
+    flag("user", None, Takes::Value),
+    flag("username", None, Takes::Value),
+    flag("v", Some('v'), Takes::Value),
+    flag("vmodule", None, Takes::Value),
+    flag("warnings-as-errors", None, Takes::Bool),
+];
+
+fn parse_bool(flag: &Flag, value: Option<&str>) -> Result<bool, Error> {
+    // the spellings go's `strconv.ParseBool` accepts
+    match value {
+        None | Some("1" | "t" | "T" | "true" | "TRUE" | "True") => Ok(true),
+        Some("0" | "f" | "F" | "false" | "FALSE" | "False") => Ok(false),
+        Some(value) => Err(Error::InvalidCommand(format!(
+            "invalid argument {:?} for \"--{}\" flag",
+            value, flag.name
+        ))),
+    }
+}
+
+/// A flag given on the command line, with its value if it has one
+type GivenFlag<'a> = (&'static Flag, Option<&'a str>);
+
+/// Read the flag `arg`, or the group of short flags, taking its value from
+/// `next` when it needs one, as kubectl's flag parser does. Returns the
+/// flags read and whether `next` was taken.
+fn read_flags<'a>(
+    arg: &'a str,
+    next: Option<&'a str>,
+) -> Result<(Vec<GivenFlag<'a>>, bool), Error> {
+    let mut flags = Vec::new();
+    let mut took_next = false;
+    if let Some(long) = arg.strip_prefix("--") {
+        let (name, value) = match long.find('=') {
+            Some(i) => (&long[..i], Some(&long[i + 1..])),
+            None => (long, None),
+        };
+        let flag = FLAGS
+            .iter()
+            .find(|flag| flag.name == name)
+            .ok_or_else(|| Error::InvalidCommand(format!("unknown flag: --{}", name)))?;
+        let value = match (flag.takes, value) {
+            (Takes::Value, None) => {
+                took_next = true;
+                Some(next.ok_or_else(|| {
+                    Error::InvalidCommand(format!("flag needs an argument: --{}", name))
+                })?)
+            }
+            (_, value) => value,
+        };
+        flags.push((flag, value));
+        return Ok((flags, took_next));
+    }
+    let mut rest = &arg[1..];
+    while let Some(c) = rest.chars().next() {
+        let flag = FLAGS
+            .iter()
+            .find(|flag| flag.short == Some(c))
+            .ok_or_else(|| {
+                Error::InvalidCommand(format!("unknown shorthand flag: '{}' in {}", c, arg))
+            })?;
+        rest = &rest[c.len_utf8()..];
+        let value = match (flag.takes, rest.strip_prefix('=')) {
+            (_, Some(value)) => Some(value),
+            (Takes::Bool, None) => None,
+            (_, None) if !rest.is_empty() => Some(rest),
+            (_, None) => {
+                took_next = true;
+                Some(next.ok_or_else(|| {
+                    Error::InvalidCommand(format!("flag needs an argument: '{}' in {}", c, arg))
+                })?)
+            }
+        };
+        if value.is_some() {
+            rest = "";
+        }
+        flags.push((flag, value));
+    }
+    Ok((flags, took_next))
+}
+
+/// A change rather than a resource, as kubectl tells them apart
+fn is_change(arg: &str) -> bool {
+    (arg.contains('=') && !arg.starts_with('=')) || (arg.ends_with('-') && arg != "-")
+}
+
+fn parse<V, S: AsRef<str>>(
+    args: &[S],
+    kind: Kind,
+    profile: Profile,
+    parse_value: fn(&str, Profile) -> Result<V, Error>,
+) -> Result<Command<V>, Error> {
+    let mut command = Command {
+        resources: Vec::new(),
+        operations: Vec::new(),
+        overwrite: false,
+        list: false,
+    };
+    let mut flags_done = false;
+    let mut index = 0;
+    while index < args.len() {
+        let arg = args[index].as_ref();
+        let arg_index = index;
+        let in_arg = |error: Error| Error::InvalidArgument {
+            index: arg_index,
+            error: Box::new(error),
+        };
+        index += 1;
+        if !flags_done && arg == "--" {
+            flags_done = true;
+        } else if !flags_done && arg.len() > 1 && arg.starts_with('-') {
+            let next = args.get(index).map(AsRef::as_ref);
+            let (flags, took_next) = read_flags(arg, next)?;
+            if took_next {
+                index += 1;
+            }
+            for (flag, value) in flags {
+                match flag.name {
+                    "overwrite" => command.overwrite = parse_bool(flag, value)?,
+                    "list" => command.list = parse_bool(flag, value)?,
+                    _ if flag.takes == Takes::Bool => {
+                        parse_bool(flag, value)?;
+                    }
+                    _ => (),
+                }
+            }
+        } else if !is_change(arg) {
+            if !command.operations.is_empty() {
+                return Err(Error::InvalidCommand(format!(
+                    "all resources must be specified before {} changes: {}",
+                    kind.name, arg
+                )));
+            }
+            command.resources.push(arg.to_string());
+        } else {
+            match arg.find('=') {
+                Some(i) if i > 0 => {
+                    let key = Key::parse_str_with_profile(&arg[..i], profile).map_err(in_arg)?;
+                    let value = parse_value(&arg[i + 1..], profile)
+                        .map_err(|err| in_arg(err.shifted(i + 1)))?;
+                    command.operations.push(Operation::Set(key, value));
+                }
+                // `is_change` makes sure of the `-`
+                _ => {
+                    let key = Key::parse_str_with_profile(&arg[..arg.len() - 1], profile)
+                        .map_err(in_arg)?;
+                    command.operations.push(Operation::Remove(key));
+                }
+            }
+        }
+    }
+    for operation in &command.operations {
+        if let Operation::Remove(removed) = operation {
+            let set = command
+                .operations
+                .iter()
+                .any(|op| matches!(op, Operation::Set(key, _) if key == removed));
+            if set {
+                return Err(Error::InvalidCommand(format!(
+                    "can not both modify and remove {} in the same command",
+                    kind.with_article
+                )));
+            }
+        }
+    }
+    if command.operations.is_empty() && !command.list {
+        return Err(Error::InvalidCommand(format!(
+            "at least one {} update is required",
+            kind.name
+        )));
+    }
+    Ok(command)
+}
+
+impl<V> Command<V> {
+    /// The resources to change, as given before the changes
+    pub fn resources(&self) -> &[String] {
+        &self.resources
+    }
+    pub fn operations(&self) -> &[Operation<V>] {
+        &self.operations
+    }
+    /// Whether keys that already have other values may be set
+    pub fn overwrite(&self) -> bool {
+        self.overwrite
+    }
+    /// Whether the labels or annotations are to be listed once changed,
+    /// which lets the command have no changes
+    pub fn list(&self) -> bool {
+        self.list
+    }
+}
+
+impl<V: Clone + PartialEq + AsRef<str>> Command<V> {
+    /// Apply the changes as kubectl does: without `overwrite`, fail if any
+    /// key would get a different value, then set the keys and remove the
+    /// keys to remove, removing missing keys being no error
+    fn apply(
+        &self,
+        map: &mut HashMap<Key, V>,
+        may_overwrite: fn(&Key) -> bool,
+        error: fn(Vec<(Key, String)>) -> Error,
+    ) -> Result<(), Error> {
+        if !self.overwrite {
+            // as kubectl collects the changes into a map, only the last
+            // value given for a key counts
+            let mut overwritten: Vec<_> = self
+                .operations
+                .iter()
+                .enumerate()
+                .filter(|&(i, op)| match op {
+                    Operation::Set(key, _) => !self.operations[i + 1..]
+                        .iter()
+                        .any(|later| matches!(later, Operation::Set(k, _) if k == key)),
+                    Operation::Remove(_) => false,
+                })
+                .filter_map(|(_, op)| match op {
+                    Operation::Set(key, value) if !may_overwrite(key) => match map.get(key) {
+                        Some(current) if current != value => {
+                            Some((key.clone(), current.as_ref().to_string()))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect();
+            if !overwritten.is_empty() {
+                overwritten.sort();
+                return Err(error(overwritten));
+            }
+        }
+        for op in &self.operations {
+            if let Operation::Set(key, value) = op {
+                map.insert(key.clone(), value.clone());
+            }
+        }
+        for op in &self.operations {
+            if let Operation::Remove(key) = op {
+                map.remove(key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LabelCommand {
+    /// Parse the arguments following `kubectl label`, with `Profile::Strict`
+    /// as kubectl validates the keys and values it is given
+    pub fn parse<S: AsRef<str>>(args: &[S]) -> Result<Self, Error> {
+        LabelCommand::parse_with_profile(args, Profile::Strict)
+    }
+    pub fn parse_with_profile<S: AsRef<str>>(args: &[S], profile: Profile) -> Result<Self, Error> {
+        parse(args, LABEL, profile, LabelValue::parse_str_with_profile)
+    }
+    /// Apply the changes to `labels`, failing with
+    /// `Error::WouldOverwriteLabels` and leaving them untouched if a label
+    /// would get a different value without `--overwrite`
+    pub fn apply_to(&self, labels: &mut LabelMap) -> Result<(), Error> {
+        self.apply(labels, |_| false, Error::WouldOverwriteLabels)
+    }
+}
+
+impl AnnotateCommand {
+    /// Parse the arguments following `kubectl annotate`, whose values may be
+    /// any string, with `Profile::Strict` as kubectl validates the keys
+    pub fn parse<S: AsRef<str>>(args: &[S]) -> Result<Self, Error> {
+        AnnotateCommand::parse_with_profile(args, Profile::Strict)
+    }
+    pub fn parse_with_profile<S: AsRef<str>>(args: &[S], profile: Profile) -> Result<Self, Error> {
+        parse(args, ANNOTATION, profile, |value, _| Ok(value.to_string()))
+    }
+    /// Apply the changes to `annotations` as `LabelCommand::apply_to` does,
+    /// except that `kubernetes.io/change-cause` may always be overwritten
+    pub fn apply_to(&self, annotations: &mut AnnotationMap) -> Result<(), Error> {
+        self.apply(
+            annotations,
+            |key| key.to_string() == CHANGE_CAUSE,
+            Error::WouldOverwriteAnnotations,
+        )
+    }
+}
@@ -0,0 +1,162 @@
+use alloc::borrow::Cow;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use crate::parser;
+use crate::types::*;
+
+/// A dotted path to a field of an object, e.g. `metadata.name`
+#[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord)]
+pub struct FieldPath(pub(crate) Vec<String>);
+
+parse_from!(FieldPath, parser::field_path_from_str);
+
+impl FieldPath {
+    pub fn segments(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        write!(f, "{}", self.0.join("."))
+    }
+}
+
+/// Gives a field selector access to the fields of an object
+///
+/// A missing field is treated like an empty one, as kubernetes does.
+pub trait FieldAccessor {
+    fn field(&self, path: &FieldPath) -> Option<Cow<'_, str>>;
+}
+
+/// Fields keyed by their dotted path
+#[cfg(feature = "std")]
+impl FieldAccessor for HashMap<String, String> {
+    fn field(&self, path: &FieldPath) -> Option<Cow<'_, str>> {
+        self.get(&path.to_string())
+            .map(|v| Cow::Borrowed(v.as_str()))
+    }
+}
+
+/// The operator of a single field selector requirement
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord)]
+pub enum FieldOperator {
+    /// `field=value`
+    Equals,
+    /// `field==value`
+    DoubleEquals,
+    /// `field!=value`
+    NotEquals,
+}
+
+impl FieldOperator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldOperator::Equals => "=",
+            FieldOperator::DoubleEquals => "==",
+            FieldOperator::NotEquals => "!=",
+        }
+    }
+}
+
+impl fmt::Display for FieldOperator {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A single requirement of a field selector, e.g. `status.phase!=Running`
+#[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord)]
+pub struct FieldRequirement {
+    pub path: FieldPath,
+    pub operator: FieldOperator,
+    /// The unescaped value
+    pub value: String,
+}
+
+impl FieldRequirement {
+    pub fn new(path: FieldPath, operator: FieldOperator, value: String) -> Self {
+        FieldRequirement {
+            path,
+            operator,
+            value,
+        }
+    }
+
+    /// Check whether the requirement holds for the given object
+    pub fn matches<A: FieldAccessor + ?Sized>(&self, object: &A) -> bool {
+        let field = object.field(&self.path);
+        let equal = field.as_deref().unwrap_or("") == self.value;
+        match self.operator {
+            FieldOperator::Equals | FieldOperator::DoubleEquals => equal,
+            FieldOperator::NotEquals => !equal,
+        }
+    }
+}
+
+impl fmt::Display for FieldRequirement {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        write!(f, "{}{}", self.path, self.operator)?;
+        for c in self.value.chars() {
+            if let '\\' | ',' | '=' = c {
+                write!(f, "\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+/// A kubernetes field selector, the AND of all of its requirements
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Default)]
+pub struct FieldSelector {
+    requirements: Vec<FieldRequirement>,
+}
+
+parse_from!(FieldSelector, parser::field_selector_from_str);
+
+impl FieldSelector {
+    pub fn new(requirements: Vec<FieldRequirement>) -> Self {
+        FieldSelector { requirements }
+    }
+    pub fn requirements(&self) -> &[FieldRequirement] {
+        &self.requirements
+    }
+    pub fn into_requirements(self) -> Vec<FieldRequirement> {
+        self.requirements
+    }
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+    pub fn add(&mut self, requirement: FieldRequirement) {
+        self.requirements.push(requirement);
+    }
+    /// Check whether every requirement holds for the given object
+    pub fn matches<A: FieldAccessor + ?Sized>(&self, object: &A) -> bool {
+        self.requirements.iter().all(|req| req.matches(object))
+    }
+}
+
+impl fmt::Display for FieldSelector {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        for (i, req) in self.requirements.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", req)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::iter::FromIterator<FieldRequirement> for FieldSelector {
+    fn from_iter<I: IntoIterator<Item = FieldRequirement>>(iter: I) -> Self {
+        FieldSelector::new(iter.into_iter().collect())
+    }
+}
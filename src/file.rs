@@ -0,0 +1,147 @@
+//! Reading labels from `.labels` files
+//!
+//! A `.labels` file holds one label per line, as `key=value` or
+//! `key: value`. Blank lines are skipped, as is anything after a `#`, and an
+//! `@include other.labels` line reads in the labels of another file, its path
+//! taken relative to the including file.
+//!
+//! ```text
+//! # shared by every service
+//! @include ../common.labels
+//! app.kubernetes.io/name=web
+//! tier: frontend  # public facing
+//! ```
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::parser::labels_file::{parse_line, FileLine};
+use crate::types::*;
+
+/// A place in a `.labels` file, lines and columns counting from 1
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    /// In characters, not bytes
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum LabelFileError {
+    /// The file at `path` could not be read, `location` being the `@include`
+    /// of it if there is one
+    Io {
+        location: Option<Location>,
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// A line is invalid, the offset of `error` being into that line
+    Invalid { location: Location, error: Error },
+    /// The file at `path` includes itself, directly or not
+    IncludeCycle { location: Location, path: PathBuf },
+}
+
+impl fmt::Display for LabelFileError {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        match self {
+            LabelFileError::Io {
+                location: Some(location),
+                path,
+                error,
+            } => write!(f, "{}: cannot read {}: {}", location, path.display(), error),
+            LabelFileError::Io {
+                location: None,
+                path,
+                error,
+            } => write!(f, "cannot read {}: {}", path.display(), error),
+            LabelFileError::Invalid { location, error } => write!(f, "{}: {}", location, error),
+            LabelFileError::IncludeCycle { location, path } => {
+                write!(f, "{}: {} includes itself", location, path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LabelFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LabelFileError::Io { error, .. } => Some(error),
+            LabelFileError::Invalid { error, .. } => Some(error),
+            LabelFileError::IncludeCycle { .. } => None,
+        }
+    }
+}
+
+/// Read the labels of a `.labels` file, including those of the files it
+/// includes in place of the `@include` lines
+pub fn labels_from_file<P: AsRef<Path>>(path: P) -> Result<Labels, LabelFileError> {
+    labels_from_file_with_profile(path, Profile::default())
+}
+
+pub fn labels_from_file_with_profile<P: AsRef<Path>>(
+    path: P,
+    profile: Profile,
+) -> Result<Labels, LabelFileError> {
+    let mut labels = Vec::new();
+    read_file(path.as_ref(), None, profile, &mut Vec::new(), &mut labels)?;
+    Ok(labels)
+}
+
+/// Read the file at `path` into `labels`, `including` holding the files
+/// being read, to catch include cycles
+fn read_file(
+    path: &Path,
+    location: Option<Location>,
+    profile: Profile,
+    including: &mut Vec<PathBuf>,
+    labels: &mut Labels,
+) -> Result<(), LabelFileError> {
+    let io_error = |error| LabelFileError::Io {
+        location: location.clone(),
+        path: path.to_path_buf(),
+        error,
+    };
+    let canonical = path.canonicalize().map_err(io_error)?;
+    if including.contains(&canonical) {
+        return Err(LabelFileError::IncludeCycle {
+            location: location.unwrap(),
+            path: path.to_path_buf(),
+        });
+    }
+    let content = std::fs::read_to_string(path).map_err(io_error)?;
+    including.push(canonical);
+    for (i, line) in content.lines().enumerate() {
+        let locate = |offset: usize| Location {
+            path: path.to_path_buf(),
+            line: i + 1,
+            column: line[..offset].chars().count() + 1,
+        };
+        match parse_line(line, profile) {
+            Ok(FileLine::Empty) => (),
+            Ok(FileLine::Label(label)) => labels.push(label),
+            Ok(FileLine::Include {
+                path: include,
+                offset,
+            }) => {
+                let included = path.parent().unwrap_or(Path::new("")).join(include);
+                read_file(&included, Some(locate(offset)), profile, including, labels)?;
+            }
+            Err(error) => {
+                return Err(LabelFileError::Invalid {
+                    location: locate(error.offset().unwrap_or(0).min(line.len())),
+                    error,
+                })
+            }
+        }
+    }
+    including.pop();
+    Ok(())
+}
@@ -0,0 +1,337 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::selector::*;
+use crate::types::*;
+
+#[derive(Debug, Clone)]
+struct KeyPostings<Id> {
+    /// Every id carrying the key, whatever its value
+    all: HashSet<Id>,
+    values: HashMap<LabelValue, HashSet<Id>>,
+}
+
+impl<Id> Default for KeyPostings<Id> {
+    fn default() -> Self {
+        KeyPostings {
+            all: HashSet::new(),
+            values: HashMap::new(),
+        }
+    }
+}
+
+/// The ids matched by a requirement, as a union of disjoint posting sets
+struct Postings<'a, Id>(Vec<&'a HashSet<Id>>);
+
+impl<'a, Id: Eq + Hash> Postings<'a, Id> {
+    fn len(&self) -> usize {
+        self.0.iter().map(|ids| ids.len()).sum()
+    }
+    fn contains(&self, id: &Id) -> bool {
+        self.0.iter().any(|ids| ids.contains(id))
+    }
+}
+
+/// An inverted index from labels to the ids of the objects carrying them
+///
+/// Selector queries are answered from the postings by set intersection and
+/// difference instead of testing every object.
+#[derive(Debug, Clone)]
+pub struct LabelIndex<Id> {
+    postings: HashMap<Key, KeyPostings<Id>>,
+    objects: HashMap<Id, LabelMap>,
+}
+
+impl<Id> Default for LabelIndex<Id> {
+    fn default() -> Self {
+        LabelIndex {
+            postings: HashMap::new(),
+            objects: HashMap::new(),
+        }
+    }
+}
+
+impl<Id: Eq + Hash + Clone> LabelIndex<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+    pub fn contains(&self, id: &Id) -> bool {
+        self.objects.contains_key(id)
+    }
+    pub fn get(&self, id: &Id) -> Option<&LabelMap> {
+        self.objects.get(id)
+    }
+    pub fn ids(&self) -> impl Iterator<Item = &Id> {
+        self.objects.keys()
+    }
+
+    fn add_posting(&mut self, id: &Id, key: &Key, value: &LabelValue) {
+        let postings = self.postings.entry(key.clone()).or_default();
+        postings.all.insert(id.clone());
+        postings
+            .values
+            .entry(value.clone())
+            .or_default()
+            .insert(id.clone());
+    }
+
+    fn remove_posting(&mut self, id: &Id, key: &Key, value: &LabelValue) {
+        if let Some(postings) = self.postings.get_mut(key) {
+            postings.all.remove(id);
+            if let Some(ids) = postings.values.get_mut(value) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    postings.values.remove(value);
+                }
+            }
+            if postings.all.is_empty() {
+                self.postings.remove(key);
+            }
+        }
+    }
+
+    /// Add or update the labels of an object, returning its previous labels
+    ///
+    /// Only the postings of labels that changed are touched.
+    pub fn insert(&mut self, id: Id, labels: LabelMap) -> Option<LabelMap> {
+        let previous = self.objects.remove(&id);
+        if let Some(ref previous) = previous {
+            for (key, value) in previous {
+                if labels.get(key) != Some(value) {
+                    self.remove_posting(&id, key, value);
+                }
+            }
+        }
+        for (key, value) in &labels {
+            let unchanged = match previous {
+                Some(ref previous) => previous.get(key) == Some(value),
+                None => false,
+            };
+            if !unchanged {
+                self.add_posting(&id, key, value);
+            }
+        }
+        self.objects.insert(id, labels);
+        previous
+    }
+
+    /// Remove an object, returning its labels
+    pub fn remove(&mut self, id: &Id) -> Option<LabelMap> {
+        let labels = self.objects.remove(id)?;
+        for (key, value) in &labels {
+            self.remove_posting(id, key, value);
+        }
+        Some(labels)
+    }
+
+    fn postings(&self, requirement: &Requirement) -> Postings<'_, Id> {
+        let postings = match self.postings.get(&requirement.key) {
+            Some(postings) => postings,
+            None => return Postings(vec![]),
+        };
+        match requirement.operator {
+            Operator::Exists | Operator::DoesNotExist => Postings(vec![&postings.all]),
+            _ => Postings(
+                requirement
+                    .values
+                    .iter()
+                    .filter_map(|value| postings.values.get(value))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The ids of all objects whose labels match the selector, in no
+    /// particular order
+    pub fn query(&self, selector: &LabelSelector) -> Vec<&Id> {
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        for req in selector.requirements() {
+            let postings = self.postings(req);
+            match req.operator {
+                Operator::Equals | Operator::DoubleEquals | Operator::In | Operator::Exists => {
+                    include.push(postings)
+                }
+                Operator::NotEquals | Operator::NotIn | Operator::DoesNotExist => {
+                    exclude.push(postings)
+                }
+            }
+        }
+        let keep = |id: &Id| {
+            include.iter().all(|postings| postings.contains(id))
+                && !exclude.iter().any(|postings| postings.contains(id))
+        };
+        // the posting sets of one requirement are disjoint, as an object has
+        // a single value per key, so the smallest can be walked directly
+        match include.iter().min_by_key(|postings| postings.len()) {
+            Some(smallest) => smallest
+                .0
+                .iter()
+                .flat_map(|ids| ids.iter())
+                .filter(|id| keep(id))
+                .collect(),
+            None => self.objects.keys().filter(|id| keep(id)).collect(),
+        }
+    }
+}
+
+/// The requirement a selector is filed under in a `SelectorIndex`
+enum Anchor<'a> {
+    /// The key must have one of these values
+    Values(&'a Key, &'a BTreeSet<LabelValue>),
+    /// The key must be present
+    Key(&'a Key),
+    /// Only negative requirements, the selector can match anything
+    None,
+}
+
+impl<'a> Anchor<'a> {
+    /// Pick the most selective positive requirement
+    fn of(selector: &'a LabelSelector) -> Self {
+        let mut anchor = Anchor::None;
+        for req in selector.requirements() {
+            match req.operator {
+                Operator::Equals | Operator::DoubleEquals | Operator::In => match anchor {
+                    Anchor::Values(_, values) if values.len() <= req.values.len() => (),
+                    _ => anchor = Anchor::Values(&req.key, &req.values),
+                },
+                Operator::Exists => {
+                    if let Anchor::None = anchor {
+                        anchor = Anchor::Key(&req.key)
+                    }
+                }
+                _ => (),
+            }
+        }
+        anchor
+    }
+}
+
+/// A reverse index answering which of many selectors match a set of labels
+///
+/// Every selector is filed under one of its positive requirements, so only
+/// the selectors filed under one of the given labels, or having no positive
+/// requirement at all, are tested.
+#[derive(Debug, Clone)]
+pub struct SelectorIndex<Id> {
+    by_value: HashMap<Key, HashMap<LabelValue, HashSet<Id>>>,
+    by_key: HashMap<Key, HashSet<Id>>,
+    unanchored: HashSet<Id>,
+    selectors: HashMap<Id, LabelSelector>,
+}
+
+impl<Id> Default for SelectorIndex<Id> {
+    fn default() -> Self {
+        SelectorIndex {
+            by_value: HashMap::new(),
+            by_key: HashMap::new(),
+            unanchored: HashSet::new(),
+            selectors: HashMap::new(),
+        }
+    }
+}
+
+impl<Id: Eq + Hash + Clone> SelectorIndex<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.selectors.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.selectors.is_empty()
+    }
+    pub fn contains(&self, id: &Id) -> bool {
+        self.selectors.contains_key(id)
+    }
+    pub fn get(&self, id: &Id) -> Option<&LabelSelector> {
+        self.selectors.get(id)
+    }
+    pub fn ids(&self) -> impl Iterator<Item = &Id> {
+        self.selectors.keys()
+    }
+
+    /// Add or replace a selector, returning the previous one
+    pub fn insert(&mut self, id: Id, selector: LabelSelector) -> Option<LabelSelector> {
+        let previous = self.remove(&id);
+        match Anchor::of(&selector) {
+            Anchor::Values(key, values) => {
+                let by_value = self.by_value.entry(key.clone()).or_default();
+                for value in values {
+                    by_value
+                        .entry(value.clone())
+                        .or_default()
+                        .insert(id.clone());
+                }
+            }
+            Anchor::Key(key) => {
+                self.by_key
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(id.clone());
+            }
+            Anchor::None => {
+                self.unanchored.insert(id.clone());
+            }
+        }
+        self.selectors.insert(id, selector);
+        previous
+    }
+
+    /// Remove a selector, returning it
+    pub fn remove(&mut self, id: &Id) -> Option<LabelSelector> {
+        let selector = self.selectors.remove(id)?;
+        match Anchor::of(&selector) {
+            Anchor::Values(key, values) => {
+                if let Some(by_value) = self.by_value.get_mut(key) {
+                    for value in values {
+                        if let Some(ids) = by_value.get_mut(value) {
+                            ids.remove(id);
+                            if ids.is_empty() {
+                                by_value.remove(value);
+                            }
+                        }
+                    }
+                    if by_value.is_empty() {
+                        self.by_value.remove(key);
+                    }
+                }
+            }
+            Anchor::Key(key) => {
+                if let Some(ids) = self.by_key.get_mut(key) {
+                    ids.remove(id);
+                    if ids.is_empty() {
+                        self.by_key.remove(key);
+                    }
+                }
+            }
+            Anchor::None => {
+                self.unanchored.remove(id);
+            }
+        }
+        Some(selector)
+    }
+
+    /// The ids of all selectors matching the labels, in no particular order
+    pub fn matching(&self, labels: &LabelMap) -> Vec<&Id> {
+        let mut candidates: HashSet<&Id> = self.unanchored.iter().collect();
+        for (key, value) in labels {
+            if let Some(ids) = self.by_value.get(key).and_then(|v| v.get(value)) {
+                candidates.extend(ids);
+            }
+            if let Some(ids) = self.by_key.get(key) {
+                candidates.extend(ids);
+            }
+        }
+        candidates
+            .into_iter()
+            .filter(|id| self.selectors[*id].matches(labels))
+            .collect()
+    }
+}
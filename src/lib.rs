@@ -1,8 +1,59 @@
-mod parser;
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+extern crate alloc;
+
+#[macro_use]
 mod types;
+#[cfg(feature = "std")]
+mod analysis;
+mod borrowed;
+mod codec;
+#[cfg(feature = "std")]
+mod command;
+mod field;
+#[cfg(feature = "std")]
+mod file;
+#[cfg(feature = "std")]
+mod index;
+#[doc(hidden)]
+pub mod literal;
+mod node;
+mod parser;
+mod sanitize;
+#[cfg(any(test, feature = "fast_validator"))]
+mod scan;
+mod selector;
+mod validate;
+pub use borrowed::*;
+pub use codec::*;
+#[cfg(feature = "std")]
+pub use command::*;
+pub use field::*;
+#[cfg(feature = "std")]
+pub use file::*;
+#[cfg(feature = "std")]
+pub use index::*;
+pub use node::*;
 pub use parser::*;
+pub use sanitize::*;
+pub use selector::*;
 pub use types::*;
 
+#[cfg(feature = "std")]
+#[cfg(test)]
+mod test_support {
+    use super::*;
+
+    /// The labels of a `key:value` or `key=value` list, as a map
+    pub(crate) fn label_map(input: &str) -> LabelMap {
+        labels_from_str_either(input)
+            .unwrap()
+            .drain(..)
+            .map(Label::into_tuple)
+            .collect()
+    }
+}
+
 #[cfg(feature = "serde_support")]
 #[cfg(test)]
 mod serde_tests {
@@ -79,6 +130,188 @@ mod serde_tests {
     }
 }
 
+#[cfg(feature = "serde_support")]
+#[cfg(test)]
+mod selector_serde_tests {
+    use super::{label_selector_from_str, ExpressionOperator, LabelSelector, LabelSelectorSpec};
+    use rstest::*;
+    use serde_yaml::{from_str, to_string};
+
+    #[test]
+    fn deser_label_selector_spec() {
+        let input = concat!(
+            "matchLabels:\n",
+            "  app: web\n",
+            "matchExpressions:\n",
+            "  - key: env\n",
+            "    operator: In\n",
+            "    values: [prod, staging]\n",
+            "  - key: example.com/partition\n",
+            "    operator: DoesNotExist\n",
+        );
+        let parsed: LabelSelectorSpec = from_str(input).unwrap();
+        assert_eq!(parsed.match_labels.len(), 1);
+        assert_eq!(parsed.match_expressions.len(), 2);
+        assert_eq!(
+            parsed.match_expressions[1].operator,
+            ExpressionOperator::DoesNotExist
+        );
+        // test the ser/de roundtrip
+        assert_eq!(parsed, from_str(&to_string(&parsed).unwrap()).unwrap());
+
+        let selector = LabelSelector::from(parsed.clone());
+        assert_eq!(
+            selector.to_string(),
+            "app=web,env in (prod,staging),!example.com/partition"
+        );
+        assert_eq!(LabelSelectorSpec::from(selector), parsed);
+    }
+
+    #[rstest]
+    #[case("matchLabels:\n  -app: web\n")]
+    #[case("matchLabels:\n  app: web!\n")]
+    #[case("matchExpressions:\n  - key: env\n    operator: In\n")]
+    #[case("matchExpressions:\n  - key: env\n    operator: Exists\n    values: [a]\n")]
+    #[case("matchExpressions:\n  - key: env\n    operator: Equals\n    values: [a]\n")]
+    fn deser_invalid_label_selector_spec(#[case] input: &str) {
+        assert!(from_str::<LabelSelectorSpec>(input).is_err());
+    }
+
+    #[test]
+    fn label_selector_to_spec() {
+        let selector = label_selector_from_str("app=web,app==api,tier!=frontend,env").unwrap();
+        let spec = LabelSelectorSpec::from(selector);
+        assert_eq!(spec.match_labels.len(), 1);
+        let operators: Vec<_> = spec.match_expressions.iter().map(|e| e.operator).collect();
+        assert_eq!(
+            operators,
+            vec![
+                ExpressionOperator::In,
+                ExpressionOperator::NotIn,
+                ExpressionOperator::Exists
+            ]
+        );
+        assert_eq!(
+            LabelSelector::from(spec).to_string(),
+            "app=web,app in (api),tier notin (frontend),env"
+        );
+    }
+
+    #[test]
+    fn normalize_label_selector_spec() {
+        let a: LabelSelectorSpec = from_str(concat!(
+            "matchLabels: {tier: api, app: web}\n",
+            "matchExpressions:\n",
+            "  - {key: env, operator: In, values: [staging, prod, prod]}\n",
+        ))
+        .unwrap();
+        let b: LabelSelectorSpec = from_str(concat!(
+            "matchExpressions:\n",
+            "  - {key: env, operator: In, values: [prod, staging]}\n",
+            "  - {key: tier, operator: In, values: [api]}\n",
+            "  - {key: app, operator: In, values: [web]}\n",
+        ))
+        .unwrap();
+        assert_ne!(a, b);
+        let a = a.normalize();
+        assert!(a.match_labels.is_empty());
+        assert_eq!(a, b.normalize());
+        assert_eq!(
+            LabelSelector::from(a).to_string(),
+            "app in (web),env in (prod,staging),tier in (api)"
+        );
+    }
+}
+
+#[cfg(feature = "serde_support")]
+#[cfg(test)]
+mod node_serde_tests {
+    use super::{LabelMap, NodeSelector, NodeSelectorOperator};
+    use crate::test_support::label_map;
+    use rstest::*;
+    use serde_yaml::{from_str, to_string};
+    use std::collections::HashMap;
+
+    #[test]
+    fn deser_node_selector() {
+        let input = concat!(
+            "nodeSelectorTerms:\n",
+            "  - matchExpressions:\n",
+            "      - key: kubernetes.io/arch\n",
+            "        operator: In\n",
+            "        values: [amd64, arm64]\n",
+            "      - key: cpus\n",
+            "        operator: Gt\n",
+            "        values: [\"4\"]\n",
+            "  - matchExpressions:\n",
+            "      - key: example.com/dedicated\n",
+            "        operator: Exists\n",
+        );
+        let parsed: NodeSelector = from_str(input).unwrap();
+        assert_eq!(parsed.node_selector_terms.len(), 2);
+        assert_eq!(
+            parsed.node_selector_terms[0].match_expressions[1].operator,
+            NodeSelectorOperator::Gt
+        );
+        // test the ser/de roundtrip
+        assert_eq!(parsed, from_str(&to_string(&parsed).unwrap()).unwrap());
+
+        assert!(parsed.matches(&label_map("kubernetes.io/arch:arm64 cpus:8")));
+        assert!(!parsed.matches(&label_map("kubernetes.io/arch:arm64 cpus:4")));
+        assert!(!parsed.matches(&label_map("kubernetes.io/arch:arm64 cpus:many")));
+        assert!(parsed.matches(&label_map("example.com/dedicated:")));
+        assert!(!parsed.matches(&label_map("kubernetes.io/arch:s390x cpus:8")));
+    }
+
+    #[test]
+    fn deser_daemonset_affinity() {
+        // node names are DNS subdomains, longer than label values may be
+        let node = format!("{}.{}.compute.internal", "n".repeat(40), "a".repeat(40));
+        let input = format!(
+            concat!(
+                "nodeSelectorTerms:\n",
+                "  - matchFields:\n",
+                "      - key: metadata.name\n",
+                "        operator: In\n",
+                "        values: [{}]\n",
+            ),
+            node
+        );
+        let parsed: NodeSelector = from_str(&input).unwrap();
+        let term = &parsed.node_selector_terms[0];
+        assert!(term.match_expressions.is_empty());
+        assert_eq!(term.match_fields[0].key.segments(), &["metadata", "name"]);
+        assert_eq!(term.match_fields[0].values[0], node);
+        // test the ser/de roundtrip keeps matchFields
+        assert_eq!(parsed, from_str(&to_string(&parsed).unwrap()).unwrap());
+
+        let fields = |name: &str| -> HashMap<String, String> {
+            vec![("metadata.name".to_string(), name.to_string())]
+                .into_iter()
+                .collect()
+        };
+        let labels = LabelMap::new();
+        assert!(parsed.matches_with_fields(&labels, &fields(&node)));
+        assert!(!parsed.matches_with_fields(&labels, &fields("node-2")));
+        assert!(!parsed.matches(&labels));
+    }
+
+    #[rstest]
+    #[case("nodeSelectorTerms:\n  - matchExpressions:\n      - {key: a, operator: In}\n")]
+    #[case(
+        "nodeSelectorTerms:\n  - matchExpressions:\n      - {key: a, operator: Gt, values: [x]}\n"
+    )]
+    #[case("nodeSelectorTerms:\n  - matchExpressions:\n      - {key: a, operator: Lt, values: [\"1\", \"2\"]}\n")]
+    #[case("nodeSelectorTerms:\n  - matchExpressions:\n      - {key: a, operator: Exists, values: [x]}\n")]
+    #[case("nodeSelectorTerms:\n  - matchFields:\n      - {key: metadata.name, operator: Exists, values: []}\n")]
+    #[case("nodeSelectorTerms:\n  - matchFields:\n      - {key: metadata.name, operator: In, values: [a, b]}\n")]
+    #[case("nodeSelectorTerms:\n  - matchFields:\n      - {key: metadata.name, operator: In, values: [Node_1]}\n")]
+    #[case("nodeSelectorTerms:\n  - matchFields:\n      - {key: metadata..name, operator: In, values: [a]}\n")]
+    fn deser_invalid_node_selector(#[case] input: &str) {
+        assert!(from_str::<NodeSelector>(input).is_err());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -210,3 +443,2016 @@ mod tests {
         assert_eq!(&annotation.value, value);
     }
 }
+
+#[cfg(test)]
+mod selector_tests {
+    use super::*;
+    use crate::test_support::label_map;
+    use rstest::*;
+
+    #[rstest]
+    #[case("", 0)]
+    #[case("app=web", 1)]
+    #[case("app=web,tier!=frontend,env==prod", 3)]
+    #[case(" app = web , tier != frontend ", 2)]
+    #[case("example.com/app=web,empty=", 2)]
+    #[case("env in (prod,staging),tier notin (frontend)", 2)]
+    #[case("partition,!partition", 2)]
+    #[case("env in(prod, staging) , ! partition,app=web", 3)]
+    #[case("x in (foo,,baz),y,z notin ()", 3)]
+    fn test_parse_selector(#[case] input: &str, #[case] num_requirements: usize) {
+        let selector = label_selector_from_str(input).unwrap();
+        assert_eq!(selector.requirements().len(), num_requirements);
+        assert_eq!(selector, LabelSelector::parse_str(input).unwrap());
+    }
+
+    #[rstest]
+    #[case("app=web,tier!=frontend,env==prod")]
+    #[case("example.com/app=web,empty=")]
+    #[case("env in (prod,staging),tier notin (frontend)")]
+    #[case("partition,!example.com/partition")]
+    fn test_selector_display_roundtrip(#[case] input: &str) {
+        let selector = label_selector_from_str(input).unwrap();
+        assert_eq!(selector.to_string(), input);
+    }
+
+    #[rstest]
+    #[case(",")]
+    #[case("app=web,")]
+    #[case("app=web=foo")]
+    #[case("-app=web")]
+    #[case("app=-web")]
+    #[case("app=!web")]
+    #[case("env in prod")]
+    #[case("env in (prod")]
+    #[case("env notin")]
+    #[case("envin (prod)")]
+    #[case("!app=web")]
+    fn test_invalid_selector(#[case] input: &str) {
+        assert!(label_selector_from_str(input).is_err());
+    }
+
+    #[rstest]
+    #[case("", "app:web", true)]
+    #[case("app=web", "app:web", true)]
+    #[case("app==web", "app:web tier:api", true)]
+    #[case("app=web", "app:api", false)]
+    #[case("app=web", "tier:api", false)]
+    #[case("tier!=frontend", "app:web", true)]
+    #[case("tier!=frontend", "tier:frontend", false)]
+    #[case("app=web,tier!=frontend", "app:web tier:api", true)]
+    #[case("app=web,tier!=frontend", "app:web tier:frontend", false)]
+    #[case("empty=", "empty:", true)]
+    #[case("env in (prod,staging)", "env:staging", true)]
+    #[case("env in (prod,staging)", "env:dev", false)]
+    #[case("env in (prod,staging)", "app:web", false)]
+    #[case("env notin (prod,staging)", "env:dev", true)]
+    #[case("env notin (prod,staging)", "app:web", true)]
+    #[case("env notin (prod,staging)", "env:prod", false)]
+    #[case("partition", "partition:", true)]
+    #[case("partition", "app:web", false)]
+    #[case("!partition", "app:web", true)]
+    #[case("!partition", "partition:a", false)]
+    fn test_selector_matches(#[case] selector: &str, #[case] input: &str, #[case] expected: bool) {
+        let selector = label_selector_from_str(selector).unwrap();
+        assert_eq!(selector.matches(&label_map(input)), expected);
+    }
+
+    #[test]
+    fn test_selector_requirement_structure() {
+        let selector = label_selector_from_str("env in (prod,staging,prod),!partition").unwrap();
+        let reqs = selector.requirements();
+        assert_eq!(reqs[0].key, Key::parse_str("env").unwrap());
+        assert_eq!(reqs[0].operator, Operator::In);
+        let values: Vec<&str> = reqs[0].values.iter().map(LabelValue::as_str).collect();
+        assert_eq!(values, vec!["prod", "staging"]);
+        assert_eq!(reqs[1].operator, Operator::DoesNotExist);
+        assert!(reqs[1].values.is_empty());
+    }
+
+    #[rstest]
+    #[case("", "")]
+    #[case("app=web", "app in (web)")]
+    #[case("tier!=frontend,app==web", "app in (web),tier notin (frontend)")]
+    #[case(
+        "  z , env in (staging, prod, prod) ,!a,app=web,app in (web)",
+        "!a,app in (web),env in (prod,staging),z"
+    )]
+    #[case(
+        "bb.example.com/x,aa.example.com/x,x",
+        "aa.example.com/x,bb.example.com/x,x"
+    )]
+    fn test_selector_normalize(#[case] input: &str, #[case] expected: &str) {
+        let selector = label_selector_from_str(input).unwrap().normalize();
+        assert!(selector.is_normalized());
+        assert_eq!(selector.to_string(), expected);
+        assert_eq!(
+            label_selector_from_str(expected).unwrap().normalize(),
+            selector
+        );
+    }
+
+    #[test]
+    fn test_normalize_keeps_key_order() {
+        // keys without a prefix still sort first, only `normalize` sorts by string
+        let key = |input: &str| Key::parse_str(input).unwrap();
+        assert!(key("x") < key("aa.example.com/x"));
+    }
+}
+
+#[cfg(test)]
+mod analysis_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("", "", true)]
+    #[case("app=web", "tier=api", true)]
+    #[case("app=web", "app=api", false)]
+    #[case("app=web", "app!=web", false)]
+    #[case("app in (web,api)", "app notin (web)", true)]
+    #[case("app in (web,api)", "app notin (web,api)", false)]
+    #[case("app", "!app", false)]
+    #[case("!app", "app notin (web)", true)]
+    #[case("!app", "app in (web)", false)]
+    #[case("app notin (a,b)", "app notin (,v0,c)", true)]
+    #[case("app,tier=api", "app!=web,!env", true)]
+    fn test_selector_overlaps(#[case] a: &str, #[case] b: &str, #[case] expected: bool) {
+        let a = label_selector_from_str(a).unwrap();
+        let b = label_selector_from_str(b).unwrap();
+        assert_eq!(a.overlaps(&b), expected);
+        assert_eq!(b.overlaps(&a), expected);
+        assert_eq!(a.intersection(&b).is_satisfiable(), expected);
+        match a.overlap_example(&b) {
+            Some(labels) => {
+                assert!(expected);
+                assert!(a.matches(&labels));
+                assert!(b.matches(&labels));
+            }
+            None => assert!(!expected),
+        }
+    }
+
+    #[rstest]
+    #[case("app=web,app=api")]
+    #[case("app,!app")]
+    #[case("app in (a,b),app notin (a,b)")]
+    fn test_unsatisfiable_selector(#[case] input: &str) {
+        let selector = label_selector_from_str(input).unwrap();
+        assert!(!selector.is_satisfiable());
+        assert!(selector.example().is_none());
+    }
+
+    #[rstest]
+    #[case("app=web", "", true)]
+    #[case("", "app=web", false)]
+    #[case("app=web,tier=api", "app=web", true)]
+    #[case("app=web", "app in (web,api)", true)]
+    #[case("app in (web,api)", "app=web", false)]
+    #[case("app=web", "app", true)]
+    #[case("app", "app=web", false)]
+    #[case("app=web", "app!=api", true)]
+    #[case("app notin (a,b)", "app!=a", true)]
+    #[case("app!=a", "app notin (a,b)", false)]
+    #[case("!app", "app!=a", true)]
+    #[case("!app", "app notin (a,b),!tier", false)]
+    #[case("app=a,app=b", "tier=impossible", true)]
+    #[case("env in (prod,staging),team=x", "env notin (dev),team", true)]
+    fn test_selector_subset(#[case] a: &str, #[case] b: &str, #[case] expected: bool) {
+        let a = label_selector_from_str(a).unwrap();
+        let b = label_selector_from_str(b).unwrap();
+        assert_eq!(a.is_subset_of(&b), expected);
+    }
+
+    #[rstest]
+    #[case("", "")]
+    #[case("env in (a,b),env=a", "env=a")]
+    #[case("env in (a,b),env!=a", "env=b")]
+    #[case("env,env in (a,b)", "env in (a,b)")]
+    #[case("env notin (a),env!=b,tier", "env notin (a,b),tier")]
+    #[case("env!=a,env", "env,env!=a")]
+    #[case("!env,env!=a", "!env")]
+    #[case("b=x,a,b=x", "a,b=x")]
+    fn test_selector_simplify(#[case] input: &str, #[case] expected: &str) {
+        let selector = label_selector_from_str(input).unwrap();
+        let simplified = selector.simplify().unwrap();
+        assert_eq!(simplified.to_string(), expected);
+        assert!(simplified.is_equivalent(&selector));
+    }
+
+    #[rstest]
+    #[case("env in (a,b),env=c")]
+    #[case("env=a,!env")]
+    fn test_selector_simplify_contradiction(#[case] input: &str) {
+        let selector = label_selector_from_str(input).unwrap();
+        assert!(selector.simplify().is_none());
+    }
+}
+
+#[cfg(test)]
+mod field_tests {
+    use super::*;
+    use rstest::*;
+    use std::collections::HashMap;
+
+    fn object() -> HashMap<String, String> {
+        vec![
+            ("metadata.name", "foo"),
+            ("metadata.namespace", "a,b=c"),
+            ("status.phase", "Pending"),
+        ]
+        .drain(..)
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+
+    #[rstest]
+    #[case("", 0)]
+    #[case("metadata.name=foo", 1)]
+    #[case("metadata.name=foo,status.phase!=Running,spec.nodeName=", 3)]
+    #[case("metadata.namespace==a\\,b\\=c", 1)]
+    #[case("spec.containers.image=nginx:1.21/x!", 1)]
+    fn test_parse_field_selector(#[case] input: &str, #[case] num_requirements: usize) {
+        let selector = field_selector_from_str(input).unwrap();
+        assert_eq!(selector.requirements().len(), num_requirements);
+        assert_eq!(selector.to_string(), input);
+        assert_eq!(selector, FieldSelector::parse_str(input).unwrap());
+    }
+
+    #[rstest]
+    #[case(",", "")]
+    #[case("metadata.name=foo,", "metadata.name=foo")]
+    #[case("a=b,,c=d", "a=b,c=d")]
+    #[case(",status.phase!=Running,,", "status.phase!=Running")]
+    fn test_field_selector_empty_terms(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(
+            field_selector_from_str(input).unwrap().to_string(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("metadata.name")]
+    #[case("metadata.name=foo, ")]
+    #[case("metadata..name=foo")]
+    #[case(".name=foo")]
+    #[case("metadata.name=a=b")]
+    #[case("metadata.name=a\\x")]
+    fn test_invalid_field_selector(#[case] input: &str) {
+        assert!(field_selector_from_str(input).is_err());
+    }
+
+    #[test]
+    fn test_field_selector_structure() {
+        let selector = field_selector_from_str("metadata.namespace!=a\\,b").unwrap();
+        let req = &selector.requirements()[0];
+        assert_eq!(req.path.segments(), &["metadata", "namespace"]);
+        assert_eq!(
+            req.path,
+            FieldPath::parse_str("metadata.namespace").unwrap()
+        );
+        assert_eq!(req.operator, FieldOperator::NotEquals);
+        assert_eq!(req.value, "a,b");
+    }
+
+    #[rstest]
+    #[case("", true)]
+    #[case("metadata.name=foo", true)]
+    #[case("metadata.name==bar", false)]
+    #[case("metadata.name=foo,status.phase!=Running", true)]
+    #[case("status.phase!=Pending", false)]
+    #[case("metadata.namespace=a\\,b\\=c", true)]
+    #[case("spec.nodeName=", true)]
+    #[case("spec.nodeName!=", false)]
+    fn test_field_selector_matches(#[case] input: &str, #[case] expected: bool) {
+        let selector = field_selector_from_str(input).unwrap();
+        assert_eq!(selector.matches(&object()), expected);
+    }
+}
+
+#[cfg(test)]
+mod node_tests {
+    use super::*;
+    use rstest::*;
+
+    fn requirement(
+        key: &str,
+        operator: NodeSelectorOperator,
+        values: &[&str],
+    ) -> NodeSelectorRequirement {
+        let values = values
+            .iter()
+            .map(|v| LabelValue::parse_str(v).unwrap())
+            .collect();
+        NodeSelectorRequirement::new(Key::parse_str(key).unwrap(), operator, values).unwrap()
+    }
+
+    #[rstest]
+    #[case(NodeSelectorOperator::Gt, "5", "6", true)]
+    #[case(NodeSelectorOperator::Gt, "5", "5", false)]
+    #[case(NodeSelectorOperator::Gt, "0", "1", true)]
+    #[case(NodeSelectorOperator::Lt, "5", "4", true)]
+    #[case(NodeSelectorOperator::Lt, "5", "10", false)]
+    #[case(NodeSelectorOperator::Lt, "5", "four", false)]
+    fn test_node_requirement_compare(
+        #[case] operator: NodeSelectorOperator,
+        #[case] bound: &str,
+        #[case] value: &str,
+        #[case] expected: bool,
+    ) {
+        let req = requirement("cpus", operator, &[bound]);
+        let mut labels = LabelMap::new();
+        assert!(!req.matches(&labels));
+        labels.insert(
+            Key::parse_str("cpus").unwrap(),
+            LabelValue::parse_str(value).unwrap(),
+        );
+        assert_eq!(req.matches(&labels), expected);
+    }
+
+    #[test]
+    fn test_node_selector_terms() {
+        let mut labels = LabelMap::new();
+        labels.insert(
+            Key::parse_str("zone").unwrap(),
+            LabelValue::parse_str("a").unwrap(),
+        );
+        let zone_a =
+            NodeSelectorTerm::new(vec![requirement("zone", NodeSelectorOperator::In, &["a"])]);
+        let no_gpu = NodeSelectorTerm::new(vec![
+            requirement("zone", NodeSelectorOperator::NotIn, &["b"]),
+            requirement("gpu", NodeSelectorOperator::DoesNotExist, &[]),
+        ]);
+        let gpu =
+            NodeSelectorTerm::new(vec![requirement("gpu", NodeSelectorOperator::Exists, &[])]);
+        assert!(zone_a.matches(&labels));
+        assert!(no_gpu.matches(&labels));
+        assert!(!gpu.matches(&labels));
+        assert!(!NodeSelectorTerm::default().matches(&labels));
+        assert!(NodeSelector::new(vec![gpu.clone(), zone_a]).matches(&labels));
+        assert!(!NodeSelector::new(vec![gpu, NodeSelectorTerm::default()]).matches(&labels));
+        assert!(!NodeSelector::default().matches(&labels));
+        assert!(NodeSelectorRequirement::new(
+            Key::parse_str("cpus").unwrap(),
+            NodeSelectorOperator::Gt,
+            vec![]
+        )
+        .is_err());
+    }
+}
+
+#[cfg(test)]
+mod index_tests {
+    use super::*;
+    use crate::test_support::label_map;
+    use rstest::*;
+
+    fn index() -> LabelIndex<u32> {
+        let mut index = LabelIndex::new();
+        index.insert(1, label_map("app:web env:prod tier:frontend"));
+        index.insert(2, label_map("app:web env:staging tier:backend"));
+        index.insert(3, label_map("app:db env:prod"));
+        index.insert(4, label_map("team:x"));
+        index
+    }
+
+    fn query(index: &LabelIndex<u32>, selector: &str) -> Vec<u32> {
+        let mut ids: Vec<u32> = index
+            .query(&label_selector_from_str(selector).unwrap())
+            .drain(..)
+            .cloned()
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[rstest]
+    #[case("", vec![1, 2, 3, 4])]
+    #[case("app=web", vec![1, 2])]
+    #[case("app=web,env=prod", vec![1])]
+    #[case("env in (prod,staging)", vec![1, 2, 3])]
+    #[case("env notin (prod)", vec![2, 4])]
+    #[case("app!=web", vec![3, 4])]
+    #[case("tier", vec![1, 2])]
+    #[case("!tier", vec![3, 4])]
+    #[case("app,!tier,env=prod", vec![3])]
+    #[case("app=missing", vec![])]
+    #[case("missing", vec![])]
+    #[case("!missing", vec![1, 2, 3, 4])]
+    fn test_index_query(#[case] selector: &str, #[case] expected: Vec<u32>) {
+        let index = index();
+        assert_eq!(query(&index, selector), expected);
+        // the index agrees with testing every object
+        let mut scanned: Vec<u32> = index
+            .ids()
+            .filter(|id| {
+                label_selector_from_str(selector)
+                    .unwrap()
+                    .matches(index.get(id).unwrap())
+            })
+            .cloned()
+            .collect();
+        scanned.sort_unstable();
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    fn test_index_update_and_remove() {
+        let mut index = index();
+        let previous = index.insert(1, label_map("app:web env:staging"));
+        assert_eq!(previous, Some(label_map("app:web env:prod tier:frontend")));
+        assert_eq!(query(&index, "env=staging"), vec![1, 2]);
+        assert_eq!(query(&index, "env=prod"), vec![3]);
+        assert_eq!(query(&index, "tier"), vec![2]);
+
+        assert_eq!(
+            index.remove(&2),
+            Some(label_map("app:web env:staging tier:backend"))
+        );
+        assert_eq!(index.remove(&2), None);
+        assert_eq!(query(&index, "tier"), Vec::<u32>::new());
+        assert_eq!(query(&index, "app=web"), vec![1]);
+        assert_eq!(index.len(), 3);
+        assert!(!index.contains(&2));
+    }
+
+    #[test]
+    fn test_selector_index() {
+        let mut index = SelectorIndex::new();
+        for (id, selector) in [
+            ("web", "app=web"),
+            ("prod-web", "app=web,env in (prod,canary)"),
+            ("any-app", "app,tier!=frontend"),
+            ("not-db", "app!=db"),
+            ("everything", ""),
+            ("db", "app=db"),
+        ] {
+            index.insert(id, label_selector_from_str(selector).unwrap());
+        }
+        let matching = |index: &SelectorIndex<&'static str>, input: &str| {
+            let mut ids: Vec<&str> = index
+                .matching(&label_map(input))
+                .drain(..)
+                .cloned()
+                .collect();
+            ids.sort_unstable();
+            ids
+        };
+        assert_eq!(
+            matching(&index, "app:web env:prod"),
+            vec!["any-app", "everything", "not-db", "prod-web", "web"]
+        );
+        assert_eq!(
+            matching(&index, "app:web tier:frontend"),
+            vec!["everything", "not-db", "web"]
+        );
+        assert_eq!(
+            matching(&index, "app:db"),
+            vec!["any-app", "db", "everything"]
+        );
+        assert_eq!(matching(&index, "team:x"), vec!["everything", "not-db"]);
+
+        let previous = index.insert("web", label_selector_from_str("app=api").unwrap());
+        assert_eq!(previous.unwrap().to_string(), "app=web");
+        assert_eq!(
+            matching(&index, "app:api"),
+            vec!["any-app", "everything", "not-db", "web"]
+        );
+        assert!(index.remove(&"everything").is_some());
+        assert!(index.remove(&"not-db").is_some());
+        assert!(index.remove(&"not-db").is_none());
+        assert_eq!(matching(&index, "team:x"), Vec::<&str>::new());
+        assert_eq!(index.len(), 4);
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("", Error::EmptyName { offset: 0 })]
+    #[case("foo/", Error::EmptyName { offset: 4 })]
+    #[case("-foo", Error::MustStartWithAlphanumeric { part: Part::Name, offset: 0 })]
+    #[case("foo-", Error::MustEndWithAlphanumeric { part: Part::Name, offset: 3 })]
+    #[case("foo-/bar", Error::MustEndWithAlphanumeric { part: Part::Prefix, offset: 3 })]
+    #[case("foo_bar/baz", Error::InvalidCharacter { part: Part::Prefix, ch: '_', offset: 3 })]
+    #[case("foo/ba r", Error::InvalidCharacter { part: Part::Name, ch: ' ', offset: 6 })]
+    #[case("foo/bar/baz", Error::InvalidCharacter { part: Part::Name, ch: '/', offset: 7 })]
+    #[case("foo..bar/baz", Error::EmptyDnsLabel { offset: 4 })]
+    #[case("/baz", Error::EmptyDnsLabel { offset: 0 })]
+    #[case("x.example.com/baz", Error::DnsLabelTooShort { len: 1, offset: 0 })]
+    #[case(&"a".repeat(64), Error::KeyNameTooLong { len: 64, offset: 0 })]
+    #[case(&format!("{}/foo", "a".repeat(64)), Error::DnsLabelTooLong { len: 64, offset: 0 })]
+    #[case(
+        &format!("{}/foo", vec!["a".repeat(63); 5].join(".")),
+        Error::PrefixTooLong { len: 319, max: 255, offset: 0 }
+    )]
+    fn test_key_errors(#[case] input: &str, #[case] expected: Error) {
+        let err = label_key_from_str(input).unwrap_err();
+        assert_eq!(err, expected);
+        assert_eq!(err.part(), expected.part());
+    }
+
+    #[rstest]
+    #[case("-foo", Error::MustStartWithAlphanumeric { part: Part::Value, offset: 0 })]
+    #[case("foo.", Error::MustEndWithAlphanumeric { part: Part::Value, offset: 3 })]
+    #[case("f/o", Error::InvalidCharacter { part: Part::Value, ch: '/', offset: 1 })]
+    #[case("fö", Error::InvalidCharacter { part: Part::Value, ch: 'ö', offset: 1 })]
+    fn test_value_errors(#[case] input: &str, #[case] expected: Error) {
+        assert_eq!(label_value_from_str(input).unwrap_err(), expected);
+    }
+
+    #[rstest]
+    #[case(
+        "foo:bar,baz:-qux",
+        Error::MustStartWithAlphanumeric { part: Part::Value, offset: 12 }
+    )]
+    #[case(
+        "foo:bar,-baz:qux",
+        Error::MustStartWithAlphanumeric { part: Part::Name, offset: 8 }
+    )]
+    #[case("foo:bar,ba$:qux", Error::InvalidCharacter { part: Part::Name, ch: '$', offset: 10 })]
+    fn test_list_errors(#[case] input: &str, #[case] expected: Error) {
+        assert_eq!(labels_from_csvstr_wcolon(input).unwrap_err(), expected);
+        assert_eq!(labels_from_str_either(input).unwrap_err(), expected);
+    }
+
+    #[rstest]
+    #[case("app=-web", Error::MustStartWithAlphanumeric { part: Part::Value, offset: 4 })]
+    #[case("app = -web", Error::MustStartWithAlphanumeric { part: Part::Value, offset: 6 })]
+    #[case("-app!=web", Error::MustStartWithAlphanumeric { part: Part::Name, offset: 0 })]
+    #[case("!app-", Error::MustEndWithAlphanumeric { part: Part::Name, offset: 4 })]
+    #[case("env in (a,b-)", Error::MustEndWithAlphanumeric { part: Part::Value, offset: 11 })]
+    fn test_selector_errors(#[case] input: &str, #[case] expected: Error) {
+        assert_eq!(label_selector_from_str(input).unwrap_err(), expected);
+    }
+
+    #[rstest]
+    #[case("foo:bar,")]
+    #[case("foo:bar,bar")]
+    #[case("foo:bar bar:baz,baz:qux")]
+    fn test_syntax_errors(#[case] input: &str) {
+        let err = labels_from_str_either(input).unwrap_err();
+        assert!(matches!(err, Error::Syntax { .. }));
+        assert!(err.part().is_none());
+        // grammar rule names are not leaked
+        assert!(!err.to_string().contains("label_"), "{}", err);
+    }
+}
+
+#[cfg(test)]
+mod profile_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("Example.COM/foo", Error::InvalidCharacter { part: Part::Prefix, ch: 'E', offset: 0 })]
+    #[case("foo.Example.com/bar", Error::InvalidCharacter { part: Part::Prefix, ch: 'E', offset: 4 })]
+    #[case(
+        &format!("{}/foo", vec!["a".repeat(63); 4].join(".")),
+        Error::PrefixTooLong { len: 255, max: 253, offset: 0 }
+    )]
+    fn test_strict_key_errors(#[case] input: &str, #[case] expected: Error) {
+        assert_eq!(
+            label_key_from_str_with_profile(input, Profile::Strict).unwrap_err(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("a.example.com/foo")]
+    #[case("example.com/Foo_Bar")]
+    #[case(&format!("{}.com/foo", "a".repeat(100)))]
+    #[case(&format!("{}.{}/foo", "a".repeat(189), "a".repeat(63)))]
+    fn test_strict_keys(#[case] input: &str) {
+        let key = label_key_from_str_with_profile(input, Profile::Strict).unwrap();
+        assert_eq!(key.to_string(), input);
+    }
+
+    #[rstest]
+    #[case("a.example.com/foo", Error::DnsLabelTooShort { len: 1, offset: 0 })]
+    #[case(
+        &format!("{}.com/foo", "a".repeat(100)),
+        Error::DnsLabelTooLong { len: 100, offset: 0 }
+    )]
+    fn test_lenient_key_errors(#[case] input: &str, #[case] expected: Error) {
+        assert_eq!(label_key_from_str(input).unwrap_err(), expected);
+        assert_eq!(
+            label_key_from_str_with_profile(input, Profile::Lenient).unwrap_err(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("Example.COM/foo")]
+    #[case(&format!("{}/foo", vec!["a".repeat(63); 4].join(".")))]
+    fn test_lenient_keys(#[case] input: &str) {
+        assert_eq!(label_key_from_str(input).unwrap().to_string(), input);
+    }
+
+    #[test]
+    fn test_value_length() {
+        let long = "a".repeat(64);
+        assert_eq!(label_value_from_str(&long).unwrap().as_str(), long);
+        assert_eq!(
+            label_value_from_str_with_profile(&long, Profile::Strict).unwrap_err(),
+            Error::ValueTooLong { len: 64, offset: 0 }
+        );
+        assert_eq!(
+            labels_from_csvstr_wcolon_with_profile(&format!("foo:{}", long), Profile::Strict)
+                .unwrap_err(),
+            Error::ValueTooLong { len: 64, offset: 4 }
+        );
+        assert_eq!(
+            LabelValue::parse_str_with_profile(&"a".repeat(63), Profile::Strict)
+                .unwrap()
+                .as_str(),
+            "a".repeat(63)
+        );
+    }
+
+    #[test]
+    fn test_key_name_length() {
+        let long = "a".repeat(64);
+        assert_eq!(KeyName::parse_str(&long).unwrap().as_str(), long);
+        assert_eq!(
+            KeyName::parse_str_with_profile(&long, Profile::Strict).unwrap_err(),
+            Error::KeyNameTooLong { len: 64, offset: 0 }
+        );
+        assert_eq!(
+            KeyName::parse_str_with_profile(&"a".repeat(63), Profile::Strict)
+                .unwrap()
+                .as_str(),
+            "a".repeat(63)
+        );
+    }
+
+    #[test]
+    fn test_strict_selector() {
+        let input = format!("app in (web,{})", "a".repeat(64));
+        assert!(label_selector_from_str(&input).is_ok());
+        assert_eq!(
+            label_selector_from_str_with_profile(&input, Profile::Strict).unwrap_err(),
+            Error::ValueTooLong {
+                len: 64,
+                offset: 12
+            }
+        );
+    }
+
+    #[cfg(feature = "serde_support")]
+    #[test]
+    fn test_serde_strict() {
+        #[derive(Debug, serde::Deserialize, serde::Serialize)]
+        struct Object {
+            #[serde(with = "strict::key")]
+            key: Key,
+            #[serde(with = "strict::value")]
+            value: LabelValue,
+            #[serde(with = "strict::label_map")]
+            labels: LabelMap,
+        }
+
+        let yaml = "key: example.com/foo\nvalue: bar\nlabels:\n  app: web\n";
+        let object: Object = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(object.labels.len(), 1);
+        assert_eq!(
+            serde_yaml::to_string(&object).unwrap(),
+            format!("---\n{}", yaml)
+        );
+
+        let yaml = "key: Example.com/foo\nvalue: bar\nlabels: {}\n";
+        assert!(serde_yaml::from_str::<Object>(yaml).is_err());
+        let yaml = format!("key: foo\nvalue: bar\nlabels:\n  app: {}\n", "a".repeat(64));
+        assert!(serde_yaml::from_str::<Object>(&yaml).is_err());
+        let yaml = "key: foo\nvalue: bar\nlabels:\n  Ex.com/app: web\n";
+        assert!(serde_yaml::from_str::<Object>(yaml).is_err());
+        // the plain instances stay lenient
+        assert!(serde_yaml::from_str::<Key>("Example.com/foo").is_ok());
+
+        #[derive(Debug, serde::Deserialize)]
+        struct Name(#[serde(with = "strict::key_name")] KeyName);
+        let name: Name = serde_yaml::from_str(&"a".repeat(63)).unwrap();
+        assert_eq!(name.0.as_str(), "a".repeat(63));
+        assert!(serde_yaml::from_str::<Name>(&"a".repeat(64)).is_err());
+        assert!(serde_yaml::from_str::<KeyName>(&"a".repeat(64)).is_ok());
+    }
+
+    #[cfg(feature = "serde_support")]
+    #[test]
+    fn test_serde_strict_nested() {
+        #[derive(Debug, serde::Deserialize, serde::Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Spec {
+            #[serde(with = "strict::label_selector")]
+            selector: LabelSelectorSpec,
+            #[serde(with = "strict::node_selector")]
+            node_selector: NodeSelector,
+            #[serde(with = "strict::annotation_map")]
+            annotations: AnnotationMap,
+        }
+
+        let spec = |selector: &str, node_key: &str, annotation_key: &str| {
+            format!(
+                concat!(
+                    "selector: {}\n",
+                    "nodeSelector:\n",
+                    "  nodeSelectorTerms:\n",
+                    "    - matchExpressions:\n",
+                    "        - {{key: {}, operator: Exists}}\n",
+                    "      matchFields:\n",
+                    "        - {{key: metadata.name, operator: In, values: [{}]}}\n",
+                    "annotations:\n",
+                    "  {}: any value\n",
+                ),
+                selector,
+                node_key,
+                "n".repeat(100),
+                annotation_key
+            )
+        };
+        let yaml = spec("{matchLabels: {app: web}}", "gpu", "example.com/note");
+        let parsed: Spec = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed.annotations.len(), 1);
+        let reparsed: Spec =
+            serde_yaml::from_str(&serde_yaml::to_string(&parsed).unwrap()).unwrap();
+        assert_eq!(reparsed.selector, parsed.selector);
+        assert_eq!(reparsed.node_selector, parsed.node_selector);
+
+        let long = "a".repeat(64);
+        for yaml in &[
+            spec(
+                &format!("{{matchLabels: {{app: {}}}}}", long),
+                "gpu",
+                "note",
+            ),
+            spec(
+                &format!(
+                    "{{matchExpressions: [{{key: app, operator: In, values: [{}]}}]}}",
+                    long
+                ),
+                "gpu",
+                "note",
+            ),
+            spec("{}", "Example.com/gpu", "note"),
+            spec("{}", "gpu", "Example.com/note"),
+        ] {
+            assert!(serde_yaml::from_str::<Spec>(yaml).is_err(), "{}", yaml);
+        }
+    }
+}
+
+#[cfg(test)]
+mod sanitize_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("", "")]
+    #[case("web", "web")]
+    #[case("v1.2.3_rc-1", "v1.2.3_rc-1")]
+    #[case("-web.", "web")]
+    #[case("feature/login", "feature-login-28ac1333")]
+    #[case("1.0+build", "1.0-build-")]
+    #[case("a//b", "a-b-")]
+    #[case("日本", "")]
+    #[case("!!", "")]
+    fn test_sanitize_value(#[case] input: &str, #[case] start: &str) {
+        let value = LabelValue::sanitize(input);
+        assert!(value.starts_with(start), "{} -> {}", input, value);
+        assert_eq!(LabelValue::sanitize(input), value);
+        LabelValue::parse_str_with_profile(&value, Profile::Strict).unwrap();
+    }
+
+    #[test]
+    fn test_sanitize_value_hashes() {
+        assert_eq!(LabelValue::sanitize("日本").len(), 8);
+        assert_ne!(LabelValue::sanitize("a/b"), LabelValue::sanitize("a+b"));
+        assert_ne!(LabelValue::sanitize("a/b"), LabelValue::sanitize("a-b"));
+
+        let long = "a".repeat(63);
+        assert_eq!(LabelValue::sanitize(&long).as_str(), long);
+        let value = LabelValue::sanitize(&"a".repeat(64));
+        assert_eq!(value.len(), 63);
+        assert_ne!(value, LabelValue::sanitize(&"a".repeat(65)));
+
+        // the cut must not leave a dash before the hash separator
+        let value = LabelValue::sanitize(&format!("{}-{}", "a".repeat(53), "b".repeat(20)));
+        assert_eq!(&value[..54], format!("{}-", "a".repeat(53)));
+        assert_eq!(value.len(), 62);
+    }
+
+    #[test]
+    fn test_sanitize_collisions() {
+        // trimming and lowercasing are not hashed
+        assert_eq!(LabelValue::sanitize("-foo-"), LabelValue::sanitize("foo"));
+        assert_eq!(
+            Key::sanitize("Example.COM/x"),
+            Key::sanitize("example.com/x")
+        );
+    }
+
+    #[rstest]
+    #[case("app", "app", &[])]
+    #[case("App_Name.v2", "App_Name.v2", &[])]
+    #[case("my app!", "my-app-", &[Adjustment::Replaced, Adjustment::Trimmed, Adjustment::Hashed])]
+    #[case("__init__", "init", &[Adjustment::Trimmed])]
+    #[case("", "811c9dc5", &[Adjustment::Hashed])]
+    fn test_sanitize_name(
+        #[case] input: &str,
+        #[case] start: &str,
+        #[case] adjustments: &[Adjustment],
+    ) {
+        let (name, report) = KeyName::sanitize_with_report(input);
+        assert!(name.starts_with(start), "{} -> {}", input, name);
+        KeyName::parse_str(&name).unwrap();
+        let expected: Vec<_> = adjustments.iter().map(|a| (Part::Name, *a)).collect();
+        assert_eq!(report.adjustments(), &expected[..]);
+        assert_eq!(report.is_unchanged(), adjustments.is_empty());
+    }
+
+    #[rstest]
+    #[case("example.com", "example.com", &[])]
+    #[case("Example.COM", "example.com", &[Adjustment::Lowercased])]
+    #[case("foo..bar.", "foo.bar", &[Adjustment::RemovedEmptyDnsLabels])]
+    #[case("my_team.example.com", "my-team-", &[Adjustment::Replaced, Adjustment::Hashed])]
+    #[case("a.example.com", "a-", &[Adjustment::Hashed])]
+    #[case("-web-.example.com", "web.example.com", &[Adjustment::Trimmed])]
+    #[case("...", "", &[Adjustment::RemovedEmptyDnsLabels, Adjustment::Hashed])]
+    fn test_sanitize_prefix(
+        #[case] input: &str,
+        #[case] start: &str,
+        #[case] adjustments: &[Adjustment],
+    ) {
+        let (prefix, report) = KeyPrefix::sanitize_with_report(input);
+        assert!(prefix.starts_with(start), "{} -> {}", input, prefix);
+        KeyPrefix::parse_str(&prefix).unwrap();
+        KeyPrefix::parse_str_with_profile(&prefix, Profile::Strict).unwrap();
+        let expected: Vec<_> = adjustments.iter().map(|a| (Part::Prefix, *a)).collect();
+        assert_eq!(report.adjustments(), &expected[..]);
+    }
+
+    #[rstest]
+    #[case(&"a".repeat(100))]
+    #[case(&vec!["a".repeat(63); 5].join("."))]
+    #[case(&vec!["ab"; 100].join("."))]
+    #[case(&format!("{}.{}", vec!["a".repeat(63); 3].join("."), "b".repeat(70)))]
+    fn test_sanitize_long_prefix(#[case] input: &str) {
+        let (prefix, report) = KeyPrefix::sanitize_with_report(input);
+        KeyPrefix::parse_str(&prefix).unwrap();
+        KeyPrefix::parse_str_with_profile(&prefix, Profile::Strict).unwrap();
+        assert!(report.contains(Part::Prefix, Adjustment::Truncated));
+        assert!(report.contains(Part::Prefix, Adjustment::Hashed));
+        assert_ne!(prefix, KeyPrefix::sanitize(&format!("{}x", input)));
+    }
+
+    #[rstest]
+    #[case("app", "app")]
+    #[case("Example.com/App", "example.com/App")]
+    #[case("/app", "app")]
+    #[case("example.com/a/b", "example.com/a-b-")]
+    #[case("Team Metadata/owner name", "team-metadata-")]
+    fn test_sanitize_key(#[case] input: &str, #[case] start: &str) {
+        let key = Key::sanitize(input);
+        assert!(key.to_string().starts_with(start), "{} -> {}", input, key);
+        Key::parse_str(&key.to_string()).unwrap();
+        Key::parse_str_with_profile(&key.to_string(), Profile::Strict).unwrap();
+    }
+
+    #[test]
+    fn test_sanitize_key_report() {
+        let (key, report) = Key::sanitize_with_report("My.Org/");
+        assert_eq!(key.to_string(), "my.org/811c9dc5");
+        assert_eq!(
+            report.adjustments(),
+            &[
+                (Part::Prefix, Adjustment::Lowercased),
+                (Part::Name, Adjustment::Hashed)
+            ]
+        );
+        let (key, report) = Key::sanitize_with_report("./app");
+        assert_eq!(key.to_string(), "app");
+        assert!(report.contains(Part::Prefix, Adjustment::Removed));
+        let (value, report) = LabelValue::sanitize_with_report("v1+2");
+        assert!(value.starts_with("v1-2-"));
+        assert_eq!(
+            report.adjustments(),
+            &[
+                (Part::Value, Adjustment::Replaced),
+                (Part::Value, Adjustment::Hashed)
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod codec_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("", "")]
+    #[case("web", "web")]
+    #[case("v1.2.3-rc.1", "v1.2.3-rc.1")]
+    #[case("x", "x")]
+    #[case("1.0.0+build.5", "x1.0.0_2Bbuild.5")]
+    #[case("dev@example.com", "xdev_40example.com")]
+    #[case("snake_case", "xsnake_5Fcase")]
+    #[case("-", "x_2D")]
+    #[case(".hidden-", "x_2Ehidden_2D")]
+    #[case("a b/c", "xa_20b_2Fc")]
+    #[case("é", "x_C3_A9")]
+    fn test_escape(#[case] input: &str, #[case] expected: &str) {
+        let value = LabelValue::escape(input).unwrap();
+        assert_eq!(value.as_str(), expected);
+        LabelValue::parse_str_with_profile(&value, Profile::Strict).unwrap();
+        assert_eq!(value.unescape().unwrap(), input);
+    }
+
+    #[test]
+    fn test_escape_too_long() {
+        assert!(LabelValue::escape(&"a".repeat(63)).is_ok());
+        assert_eq!(
+            LabelValue::escape(&"a".repeat(64)).unwrap_err(),
+            Error::ValueTooLong { len: 64, offset: 0 }
+        );
+        assert_eq!(
+            LabelValue::escape(&"@".repeat(21)).unwrap_err(),
+            Error::ValueTooLong { len: 64, offset: 0 }
+        );
+    }
+
+    #[rstest]
+    #[case("a_2D", 0)]
+    #[case("x_2", 1)]
+    #[case("xab_2d", 3)]
+    #[case("xa_GG", 2)]
+    #[case("xa_FFb", 2)]
+    #[case("xa_C3b", 2)]
+    fn test_unescape_errors(#[case] input: &str, #[case] offset: usize) {
+        let value = LabelValue::parse_str(input).unwrap();
+        assert_eq!(
+            value.unescape().unwrap_err(),
+            Error::InvalidEscape { offset }
+        );
+    }
+}
+
+#[cfg(test)]
+mod value_codec_tests {
+    use super::*;
+    use rstest::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn roundtrip<C: LabelValueCodec>(codec: C, value: C::Value, encoded: &str)
+    where
+        C::Value: PartialEq + std::fmt::Debug,
+    {
+        let label_value = codec.encode(&value).unwrap();
+        assert_eq!(label_value.as_str(), encoded);
+        LabelValue::parse_str_with_profile(&label_value, Profile::Strict).unwrap();
+        assert_eq!(codec.decode(&label_value).unwrap(), value);
+    }
+
+    fn decode_err<C: LabelValueCodec>(codec: C, input: &str) -> Error {
+        match codec.decode(&LabelValue::parse_str(input).unwrap()) {
+            Ok(_) => panic!("{} decoded", input),
+            Err(err) => err,
+        }
+    }
+
+    #[rstest]
+    #[case(0, "0")]
+    #[case(42, "42")]
+    #[case(-42, "n42")]
+    #[case(i64::MAX, "9223372036854775807")]
+    #[case(i64::MIN, "n9223372036854775808")]
+    fn test_integer(#[case] value: i64, #[case] encoded: &str) {
+        roundtrip(IntegerCodec, value, encoded);
+    }
+
+    #[rstest]
+    #[case("n0")]
+    #[case("007")]
+    #[case("n07")]
+    #[case("n")]
+    #[case("4x")]
+    #[case("9223372036854775808")]
+    #[case("n9223372036854775809")]
+    fn test_integer_errors(#[case] input: &str) {
+        assert_eq!(
+            decode_err(IntegerCodec, input),
+            Error::InvalidEncoding("integer")
+        );
+    }
+
+    #[test]
+    fn test_bool() {
+        roundtrip(BoolCodec, true, "true");
+        roundtrip(BoolCodec, false, "false");
+        assert_eq!(
+            decode_err(BoolCodec, "True"),
+            Error::InvalidEncoding("boolean")
+        );
+        assert_eq!(
+            decode_err(BoolCodec, "1"),
+            Error::InvalidEncoding("boolean")
+        );
+    }
+
+    #[rstest]
+    #[case("1.2.3", "1.2.3")]
+    #[case("1.0.0-rc.1", "1.0.0-rc.1")]
+    #[case("1.0.0+build.5", "1.0.0_build.5")]
+    #[case("0.1.0-alpha.beta+exp.sha.5114f85", "0.1.0-alpha.beta_exp.sha.5114f85")]
+    fn test_semver(#[case] version: &str, #[case] encoded: &str) {
+        roundtrip(SemverCodec, version.to_string(), encoded);
+    }
+
+    #[rstest]
+    #[case("1.2")]
+    #[case("01.2.3")]
+    #[case("1.2.3-01")]
+    #[case("1.2.3-")]
+    #[case("1.2.3+")]
+    #[case("1.2.3+a+b")]
+    #[case("v1.2.3")]
+    fn test_semver_errors(#[case] version: &str) {
+        assert_eq!(
+            SemverCodec.encode(&version.to_string()).unwrap_err(),
+            Error::InvalidEncoding("semver version")
+        );
+    }
+
+    #[test]
+    fn test_semver_not_a_value() {
+        // valid semver, but values must end with an alphanumeric character
+        assert_eq!(
+            SemverCodec.encode(&"1.2.3-rc-".to_string()).unwrap_err(),
+            Error::MustEndWithAlphanumeric {
+                part: Part::Value,
+                offset: 8
+            }
+        );
+        assert_eq!(
+            decode_err(SemverCodec, "1.2.3_a_b"),
+            Error::InvalidEncoding("semver version")
+        );
+    }
+
+    #[test]
+    fn test_escaped() {
+        roundtrip(
+            EscapedCodec,
+            "dev@example.com".to_string(),
+            "xdev_40example.com",
+        );
+    }
+
+    #[rstest]
+    #[case(0, 0, "1970-01-01T00-00-00Z")]
+    #[case(1_625_061_909, 0, "2021-06-30T14-05-09Z")]
+    #[case(1_625_061_909, 250_000_000, "2021-06-30T14-05-09.25Z")]
+    #[case(1_625_061_909, 1, "2021-06-30T14-05-09.000000001Z")]
+    #[case(951_825_600, 0, "2000-02-29T12-00-00Z")]
+    #[case(253_402_300_799, 0, "9999-12-31T23-59-59Z")]
+    #[case(-1, 500_000_000, "1969-12-31T23-59-59.5Z")]
+    #[case(-62_135_596_800, 0, "0001-01-01T00-00-00Z")]
+    fn test_timestamp(#[case] secs: i64, #[case] nanos: u32, #[case] encoded: &str) {
+        let time = if secs >= 0 {
+            UNIX_EPOCH + Duration::from_secs(secs as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+        };
+        roundtrip(
+            TimestampCodec,
+            time + Duration::from_nanos(nanos.into()),
+            encoded,
+        );
+    }
+
+    #[rstest]
+    #[case("2021-06-30T14-05-09")]
+    #[case("2021-06-30t14-05-09Z")]
+    #[case("2021-06-30T14-05-9Z")]
+    #[case("2021-06-30T24-00-00Z")]
+    #[case("2021-06-30T14-60-00Z")]
+    #[case("2021-02-29T00-00-00Z")]
+    #[case("2100-02-29T00-00-00Z")]
+    #[case("2021-13-01T00-00-00Z")]
+    #[case("2021-06-00T00-00-00Z")]
+    #[case("2021-06-30T14-05-09.Z")]
+    #[case("2021-06-30T14-05-09.1234567890Z")]
+    #[case("2021-06-30T14-05-09-01Z")]
+    fn test_timestamp_errors(#[case] input: &str) {
+        assert_eq!(
+            decode_err(TimestampCodec, input),
+            Error::InvalidEncoding("timestamp")
+        );
+    }
+
+    #[test]
+    fn test_timestamp_out_of_range() {
+        let time = UNIX_EPOCH + Duration::from_secs(253_402_300_800);
+        assert_eq!(
+            TimestampCodec.encode(&time).unwrap_err(),
+            Error::InvalidEncoding("timestamp")
+        );
+    }
+}
+
+#[cfg(test)]
+mod borrowed_tests {
+    use super::*;
+    use rstest::*;
+
+    fn within(input: &str, part: &str) -> bool {
+        let range = input.as_bytes().as_ptr_range();
+        part.is_empty() || range.contains(&part.as_ptr())
+    }
+
+    #[rstest]
+    #[case("app")]
+    #[case("example.com/app")]
+    #[case("Example.COM/app_v2")]
+    fn test_key_ref(#[case] input: &str) {
+        let key = label_key_ref_from_str(input).unwrap();
+        assert!(within(input, key.name()));
+        if let Some(prefix) = key.prefix() {
+            assert!(within(input, prefix));
+        }
+        assert_eq!(key.to_string(), input);
+        let owned = label_key_from_str(input).unwrap();
+        assert_eq!(key.to_owned(), owned);
+        assert_eq!(key, owned);
+        assert_eq!(Key::from(key), owned);
+    }
+
+    #[rstest]
+    #[case("", Error::EmptyName { offset: 0 })]
+    #[case("foo-/bar", Error::MustEndWithAlphanumeric { part: Part::Prefix, offset: 3 })]
+    fn test_key_ref_errors(#[case] input: &str, #[case] expected: Error) {
+        assert_eq!(KeyRef::parse_str(input).unwrap_err(), expected);
+    }
+
+    #[test]
+    fn test_value_ref() {
+        let input = "web-1";
+        let value = LabelValueRef::parse_str(input).unwrap();
+        assert_eq!(value.as_str().as_ptr(), input.as_ptr());
+        assert_eq!(value, LabelValue::parse_str(input).unwrap());
+        assert_eq!(LabelValueRef::parse_str("").unwrap().as_str(), "");
+        assert_eq!(
+            LabelValueRef::parse_str_with_profile(&"a".repeat(64), Profile::Strict).unwrap_err(),
+            Error::ValueTooLong { len: 64, offset: 0 }
+        );
+    }
+
+    #[rstest]
+    #[case("foo=bar", "foo", "bar")]
+    #[case("example.com/foo=", "example.com/foo", "")]
+    fn test_label_ref(#[case] input: &str, #[case] key: &str, #[case] value: &str) {
+        let label = label_ref_from_envstr(input).unwrap();
+        assert_eq!(label.key.to_string(), key);
+        assert_eq!(label.value.as_str(), value);
+        assert!(within(input, label.value.as_str()));
+        assert_eq!(label.to_owned(), label_from_envstr(input).unwrap());
+    }
+
+    #[test]
+    fn test_labels_ref() {
+        let input = "foo:bar,example.com/baz:qux";
+        let labels = labels_ref_from_csvstr_wcolon(input).unwrap();
+        let owned: Vec<Label> = labels.iter().map(|label| label.to_owned()).collect();
+        assert_eq!(owned, labels_from_csvstr_wcolon(input).unwrap());
+        assert!(labels.iter().all(|label| within(input, label.key.name())));
+
+        let input = "foo:bar baz:qux";
+        assert_eq!(labels_ref_from_wsvstr_wcolon(input).unwrap().len(), 2);
+        assert_eq!(labels_ref_from_str_either(input).unwrap().len(), 2);
+        assert_eq!(
+            label_ref_from_str_wcolon("foo:bar").unwrap().key.name(),
+            "foo"
+        );
+        assert_eq!(labels_ref_from_envstr("a=b c=d").unwrap().len(), 2);
+        assert_eq!(
+            labels_ref_from_csvstr_wcolon("foo:bar,-baz:qux").unwrap_err(),
+            Error::MustStartWithAlphanumeric {
+                part: Part::Name,
+                offset: 8
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_ref_order() {
+        let mut keys: Vec<_> = ["b", "aa.io/z", "a", "aa/b"]
+            .iter()
+            .map(|k| KeyRef::parse_str(k).unwrap())
+            .collect();
+        keys.sort();
+        let mut owned: Vec<Key> = keys.iter().map(KeyRef::to_owned).collect();
+        owned.sort();
+        assert_eq!(keys, owned);
+        let keys: Vec<_> = keys.iter().map(|k| k.to_string()).collect();
+        assert_eq!(keys, ["a", "b", "aa/b", "aa.io/z"]);
+    }
+}
+
+#[cfg(test)]
+mod backend_tests {
+    use super::*;
+    use rstest::*;
+
+    const ALPHABET: &[&str] = &["a", "Z", "0", "-", "_", ".", "/", " ", "é"];
+
+    /// Every string of up to `len` symbols of the alphabet
+    fn exhaustive(len: usize) -> Vec<String> {
+        let mut res = vec![String::new()];
+        let mut last = vec![String::new()];
+        for _ in 0..len {
+            last = last
+                .iter()
+                .flat_map(|s| ALPHABET.iter().map(move |c| format!("{}{}", s, c)))
+                .collect();
+            res.extend(last.iter().cloned());
+        }
+        res
+    }
+
+    /// Strings around the length limits, with the odd invalid character
+    fn around_limits() -> Vec<String> {
+        let mut res = Vec::new();
+        for &len in &[1, 2, 62, 63, 64, 65] {
+            let name = "n".repeat(len);
+            res.push(name.clone());
+            res.push(format!("{}_", name));
+            res.push(format!("{}/{}", name, name));
+            res.push(format!("aa.{}.io/x", name));
+            res.push(format!("aa.{}-.io/x_", name));
+        }
+        for &len in &[251, 252, 253, 254, 255, 256, 300] {
+            let labels = vec!["p".repeat(62); len / 63 + 1].join(".");
+            let prefix = &labels[..len];
+            res.push(prefix.to_string());
+            res.push(format!("{}/name", prefix));
+            res.push(format!("{}/Name", prefix.to_uppercase()));
+            res.push(format!("{}_/name", prefix));
+        }
+        res
+    }
+
+    /// Deterministic pseudo random strings over the alphabet
+    fn random(count: usize) -> Vec<String> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move |bound: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+        (0..count)
+            .map(|_| {
+                let len = next(80);
+                (0..len)
+                    .map(|_| match next(20) {
+                        0..=8 => "a",
+                        9 => "B",
+                        10..=11 => ".",
+                        n => ALPHABET[n as usize - 12 + 1],
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[rstest]
+    #[case(Profile::Strict)]
+    #[case(Profile::Lenient)]
+    fn test_backends_agree(#[case] profile: Profile) {
+        let inputs = exhaustive(3)
+            .into_iter()
+            .chain(around_limits())
+            .chain(random(5_000));
+        for input in inputs {
+            assert_eq!(
+                scan::key(&input, profile),
+                parser::pest_backend::key(&input, profile),
+                "key {:?}",
+                input
+            );
+            assert_eq!(
+                scan::prefix(&input, profile),
+                parser::pest_backend::prefix(&input, profile),
+                "prefix {:?}",
+                input
+            );
+            assert_eq!(
+                scan::name(&input, profile),
+                parser::pest_backend::name(&input, profile),
+                "name {:?}",
+                input
+            );
+            assert_eq!(
+                scan::value(&input, profile),
+                parser::pest_backend::value(&input, profile),
+                "value {:?}",
+                input
+            );
+        }
+    }
+
+    /// The checks behind `key!` and `label_value!` accept what `parse_str` does
+    #[test]
+    fn test_literal_checks_agree() {
+        for input in exhaustive(3).into_iter().chain(around_limits()) {
+            assert_eq!(
+                literal::check_key(&input).is_ok(),
+                Key::parse_str(&input).is_ok(),
+                "key {:?}",
+                input
+            );
+            assert_eq!(
+                literal::check_value(&input).is_ok(),
+                LabelValue::parse_str(&input).is_ok(),
+                "value {:?}",
+                input
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod literal_tests {
+    use super::*;
+    use rstest::*;
+
+    const TEAM: &str = "example.com/team";
+
+    #[test]
+    fn test_key_macro() {
+        assert_eq!(
+            key!("app.kubernetes.io/name"),
+            Key::parse_str("app.kubernetes.io/name").unwrap()
+        );
+        assert_eq!(key!("tier"), Key::parse_str("tier").unwrap());
+        assert_eq!(key!(TEAM), Key::parse_str(TEAM).unwrap());
+    }
+
+    #[rstest]
+    #[case(label_value!("prod"), "prod")]
+    #[case(label_value!(""), "")]
+    #[case(label_value!("v1.2_3-rc"), "v1.2_3-rc")]
+    fn test_label_value_macro(#[case] value: LabelValue, #[case] expected: &str) {
+        assert_eq!(value, LabelValue::parse_str(expected).unwrap());
+    }
+
+    #[test]
+    fn test_labels_macro() {
+        let labels = labels! {
+            "app" => "web",
+            "app.kubernetes.io/tier" => "api",
+        };
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[&key!("app")], label_value!("web"));
+        assert_eq!(labels[&key!("app.kubernetes.io/tier")], label_value!("api"));
+        assert!(labels! {}.is_empty());
+    }
+
+    #[rstest]
+    #[case("-app", "must start with an alphanumeric character")]
+    #[case("app.io/", "key name must not be empty")]
+    #[case("a.io/app", "DNS label in key prefix is too short")]
+    #[case("app..io/app", "key prefix must not contain an empty DNS label")]
+    #[case("app io", "invalid character")]
+    fn test_check_key(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(literal::check_key(input), Err(expected));
+    }
+}
+
+#[cfg(test)]
+mod collect_errors_tests {
+    use super::*;
+    use rstest::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_csv_collects_every_error() {
+        let input = "app:web,-bad:x,tier:api,,zone:a b";
+        let (labels, errors) = labels_from_csvstr_wcolon_collect_errors(input);
+        assert_eq!(
+            labels,
+            labels_from_csvstr_wcolon("app:web,tier:api").unwrap()
+        );
+        let found: Vec<_> = errors
+            .iter()
+            .map(|err| (err.index, err.span.clone(), err.error.offset()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, 8..14, Some(8)),
+                (3, 24..24, Some(24)),
+                (4, 25..33, Some(31)),
+            ]
+        );
+        assert_eq!(
+            errors[0].error,
+            Error::MustStartWithAlphanumeric {
+                part: Part::Name,
+                offset: 8
+            }
+        );
+    }
+
+    #[test]
+    fn test_envstr_collects_every_error() {
+        let input = "  app=web  a.b/c=d tier=api_ x=y  ";
+        let (labels, errors) = labels_from_envstr_collect_errors(input);
+        assert_eq!(labels, labels_from_envstr("app=web x=y").unwrap());
+        let found: Vec<_> = errors
+            .iter()
+            .map(|err| (err.index, &input[err.span.clone()], err.error.clone()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, "a.b/c=d", Error::DnsLabelTooShort { len: 1, offset: 11 }),
+                (
+                    2,
+                    "tier=api_",
+                    Error::MustEndWithAlphanumeric {
+                        part: Part::Value,
+                        offset: 27
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("app:web")]
+    #[case("app:web,tier:api")]
+    #[case("app:web tier:api")]
+    #[case("app:web  tier:api")]
+    #[case("app:web, tier:api")]
+    #[case("app:web tier:api,zone:a")]
+    #[case(" app:web")]
+    #[case("app:web ")]
+    #[case("app:web,")]
+    #[case("")]
+    #[case("a.io/app:web,tier=api")]
+    fn test_either_agrees_with_first_error(#[case] input: &str) {
+        let (labels, errors) = labels_from_str_either_collect_errors(input);
+        match labels_from_str_either(input) {
+            Ok(expected) => {
+                assert_eq!(errors, vec![]);
+                assert_eq!(labels, expected);
+            }
+            Err(_) => assert!(!errors.is_empty()),
+        }
+    }
+
+    #[rstest]
+    #[case("app=web tier=api", 0)]
+    #[case("\tapp=web\n", 0)]
+    #[case("", 1)]
+    #[case("  ", 1)]
+    #[case("app=web =x tier=", 1)]
+    fn test_envstr_error_count(#[case] input: &str, #[case] count: usize) {
+        let (_, errors) = labels_from_envstr_collect_errors(input);
+        assert_eq!(errors.len(), count);
+        assert_eq!(count == 0, labels_from_envstr(input).is_ok());
+    }
+
+    #[test]
+    fn test_label_map_from_string_map() {
+        let map: HashMap<String, String> = vec![
+            ("app", "web"),
+            ("tier", "api-"),
+            ("-zone", "eu"),
+            ("example.com/team", "core"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let (labels, errors) = label_map_from_string_map(&map);
+        assert_eq!(
+            labels,
+            labels! { "app" => "web", "example.com/team" => "core" }
+        );
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "-zone".to_string(),
+                    Error::MustStartWithAlphanumeric {
+                        part: Part::Name,
+                        offset: 0
+                    }
+                ),
+                (
+                    "tier".to_string(),
+                    Error::MustEndWithAlphanumeric {
+                        part: Part::Value,
+                        offset: 3
+                    }
+                ),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod conflict_tests {
+    use super::*;
+    use rstest::*;
+
+    fn values(labels: &[Label]) -> Vec<String> {
+        labels
+            .iter()
+            .map(|label| format!("{}={}", label.key, label.value))
+            .collect()
+    }
+
+    #[rstest]
+    #[case(ConflictPolicy::FirstWins, &["a=1", "b=2"])]
+    #[case(ConflictPolicy::LastWins, &["a=3", "b=2"])]
+    fn test_duplicates_resolved(#[case] policy: ConflictPolicy, #[case] expected: &[&str]) {
+        let (labels, conflicts) =
+            labels_from_csvstr_wcolon_with_policy("a:1,b:2,a:3", policy).unwrap();
+        assert_eq!(values(&labels), expected);
+        assert_eq!(
+            conflicts,
+            vec![Conflict {
+                kind: ConflictKind::Duplicate,
+                first: 0..1,
+                second: 8..9,
+            }]
+        );
+    }
+
+    #[rstest]
+    #[case("a=1 b=2 a=3", Error::DuplicateKey { first: 0, offset: 8 })]
+    #[case("app=1 ex.io/app=2 App=3", Error::KeyCaseCollision { first: 0, offset: 18 })]
+    #[case("ex.io/app=1 EX.io/app=2", Error::KeyCaseCollision { first: 0, offset: 12 })]
+    fn test_conflict_errors(#[case] input: &str, #[case] expected: Error) {
+        assert_eq!(
+            labels_from_envstr_with_policy(input, ConflictPolicy::Error),
+            Err(expected)
+        );
+        assert!(labels_from_envstr(input).is_ok());
+    }
+
+    #[test]
+    fn test_case_collisions_kept() {
+        let (labels, conflicts) = labels_from_wsvstr_wcolon_with_policy(
+            "app:web App:api app:db",
+            ConflictPolicy::LastWins,
+        )
+        .unwrap();
+        assert_eq!(values(&labels), vec!["app=db", "App=api"]);
+        assert_eq!(
+            conflicts,
+            vec![
+                Conflict {
+                    kind: ConflictKind::CaseCollision,
+                    first: 0..3,
+                    second: 8..11,
+                },
+                Conflict {
+                    kind: ConflictKind::Duplicate,
+                    first: 0..3,
+                    second: 16..19,
+                },
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("a:1,b:2")]
+    #[case("a:1 b:2")]
+    #[case("a:1")]
+    fn test_no_conflicts(#[case] input: &str) {
+        let (labels, conflicts) =
+            labels_from_str_either_with_policy(input, ConflictPolicy::Error).unwrap();
+        assert_eq!(labels, labels_from_str_either(input).unwrap());
+        assert_eq!(conflicts, vec![]);
+    }
+
+    #[test]
+    fn test_syntax_errors_first() {
+        assert_eq!(
+            labels_from_csvstr_wcolon_with_policy("a:1,a:1,-b:2", ConflictPolicy::Error),
+            Err(Error::MustStartWithAlphanumeric {
+                part: Part::Name,
+                offset: 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_profile_and_policy() {
+        let input = format!("a:{}", "v".repeat(64));
+        assert!(labels_from_str_either_with_policy(&input, ConflictPolicy::Error).is_ok());
+        assert_eq!(
+            labels_from_str_either_with_profile_and_policy(
+                &input,
+                Profile::Strict,
+                ConflictPolicy::Error
+            ),
+            Err(Error::ValueTooLong { len: 64, offset: 2 })
+        );
+    }
+
+    #[rstest]
+    #[case(ConflictPolicy::FirstWins, "1")]
+    #[case(ConflictPolicy::LastWins, "3")]
+    fn test_label_map_with_policy(#[case] policy: ConflictPolicy, #[case] expected: &str) {
+        let labels = labels_from_envstr("a=1 App=2 a=3").unwrap();
+        let (map, conflicts) = label_map_from_labels_with_policy(labels, policy).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[&key!("a")].as_str(), expected);
+        assert_eq!(
+            conflicts,
+            vec![Conflict {
+                kind: ConflictKind::Duplicate,
+                first: 0..1,
+                second: 2..3,
+            }]
+        );
+        let labels = labels_from_envstr("app=1 App=2").unwrap();
+        assert_eq!(
+            label_map_from_labels_with_policy(labels, ConflictPolicy::Error),
+            Err(Error::KeyCaseCollision {
+                first: 0,
+                offset: 1
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod annotation_list_tests {
+    use super::*;
+    use rstest::*;
+
+    fn annotation(key: &str, value: &str) -> Annotation {
+        Annotation::new(Key::parse_str(key).unwrap(), value.to_string())
+    }
+
+    #[rstest]
+    #[case("", vec![])]
+    #[case("a=1", vec![("a", "1")])]
+    #[case("a=1,b=x y", vec![("a", "1"), ("b", "x y")])]
+    #[case("a=1\nb=2\n", vec![("a", "1"), ("b", "2")])]
+    #[case("a=1\r\n\r\nb=2", vec![("a", "1"), ("b", "2")])]
+    #[case("a=,b=", vec![("a", ""), ("b", "")])]
+    #[case("url=http://x.io/?q=1", vec![("url", "http://x.io/?q=1")])]
+    #[case("a=say \"hi\"", vec![("a", "say \"hi\"")])]
+    #[case(r#"a="1,2",b=3"#, vec![("a", "1,2"), ("b", "3")])]
+    #[case(r#"ex.io/a="line\nnext \"q\" \\""#, vec![("ex.io/a", "line\nnext \"q\" \\")])]
+    #[case(r#"a="""#, vec![("a", "")])]
+    fn test_annotations_from_str(#[case] input: &str, #[case] expected: Vec<(&str, &str)>) {
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(key, value)| annotation(key, value))
+            .collect();
+        assert_eq!(annotations_from_str(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("-a=1", Error::MustStartWithAlphanumeric { part: Part::Name, offset: 0 })]
+    #[case("a=1,b c=2", Error::InvalidCharacter { part: Part::Name, ch: ' ', offset: 5 })]
+    #[case("a=1\nex..io/b=2", Error::EmptyDnsLabel { offset: 7 })]
+    fn test_annotations_errors(#[case] input: &str, #[case] expected: Error) {
+        assert_eq!(annotations_from_str(input), Err(expected));
+    }
+
+    #[rstest]
+    #[case(r#"a="1"2"#)]
+    #[case(r#"a="1"#)]
+    #[case(r#"a="\t""#)]
+    #[case("a")]
+    fn test_annotations_syntax_errors(#[case] input: &str) {
+        assert!(matches!(
+            annotations_from_str(input),
+            Err(Error::Syntax { .. })
+        ));
+    }
+
+    #[rstest]
+    #[case("1", "a=1")]
+    #[case("", "a=")]
+    #[case("x y=z", "a=x y=z")]
+    #[case("in \"quotes\"", "a=in \"quotes\"")]
+    #[case("1,2", r#"a="1,2""#)]
+    #[case("\"1\"", r#"a="\"1\"""#)]
+    #[case("two\nlines\\", r#"a="two\nlines\\""#)]
+    fn test_annotation_display(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(annotation("a", value).to_string(), expected);
+    }
+
+    #[test]
+    fn test_annotations_round_trip() {
+        let annotations = vec![
+            annotation("a", "plain"),
+            annotation("ex.io/b", "1,2\r\n\"3\"\\n"),
+            annotation("c", ""),
+            annotation("d", "\""),
+        ];
+        let written = annotations_to_string(&annotations);
+        assert_eq!(annotations_from_str(&written).unwrap(), annotations);
+    }
+}
+
+#[cfg(test)]
+mod file_tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    /// A directory removed once the test using it is done, passed or not
+    struct TempDir(PathBuf);
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A fresh directory holding the given files
+    fn dir_with(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("klap-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, content) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        TempDir(dir)
+    }
+
+    #[test]
+    fn test_labels_file() {
+        let dir = dir_with(
+            "plain",
+            &[(
+                "app.labels",
+                "# shared labels\n\napp.kubernetes.io/name=web\r\n  tier: api  # public\nzone:\nempty=\n\t# done\n",
+            )],
+        );
+        assert_eq!(
+            labels_from_file(dir.join("app.labels")).unwrap(),
+            labels_from_envstr("app.kubernetes.io/name=web tier=api zone= empty=").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_labels_file_includes() {
+        let dir = dir_with(
+            "includes",
+            &[
+                ("common.labels", "team=core\n@include nested/more.labels\n"),
+                ("nested/more.labels", "cost-center=42 # billing\n"),
+                (
+                    "svc/app.labels",
+                    "app=web\n@include ../common.labels  # shared\ntier=api\n",
+                ),
+            ],
+        );
+        assert_eq!(
+            labels_from_file(dir.join("svc/app.labels")).unwrap(),
+            labels_from_envstr("app=web team=core cost-center=42 tier=api").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_labels_file_errors() {
+        let dir = dir_with(
+            "errors",
+            &[
+                ("bad.labels", "app=web\n\n  tier: api-\n"),
+                ("unicode.labels", "# é\nré=1\n"),
+                ("missing.labels", "a=b\n  @include nope.labels\n"),
+                ("cycle.labels", "@include other.labels\n"),
+                ("other.labels", "a=b\n@include cycle.labels\n"),
+            ],
+        );
+        let error = |name: &str| {
+            labels_from_file(dir.join(name))
+                .unwrap_err()
+                .to_string()
+                .replace(&format!("{}/", dir.display()), "")
+        };
+        assert_eq!(
+            error("bad.labels"),
+            "bad.labels:3:12: value must end with an alphanumeric character (at offset 11)"
+        );
+        match labels_from_file(dir.join("unicode.labels")).unwrap_err() {
+            LabelFileError::Invalid { location, error } => {
+                assert_eq!((location.line, location.column), (2, 2));
+                assert_eq!(error.offset(), Some(1));
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+        assert!(error("missing.labels").starts_with("missing.labels:2:3: cannot read nope.labels"));
+        assert_eq!(
+            error("cycle.labels"),
+            "other.labels:2:1: cycle.labels includes itself"
+        );
+        assert!(matches!(
+            labels_from_file(dir.join("none.labels")),
+            Err(LabelFileError::Io { location: None, .. })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod command_tests {
+    use super::*;
+    use rstest::*;
+
+    fn annotations(entries: &[(&str, &str)]) -> AnnotationMap {
+        entries
+            .iter()
+            .map(|(key, value)| (Key::parse_str(key).unwrap(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_label_command() {
+        let command = LabelCommand::parse(&[
+            "pods",
+            "web",
+            "--overwrite",
+            "tier=api",
+            "ex.io/team=",
+            "zone-",
+        ])
+        .unwrap();
+        assert_eq!(command.resources(), ["pods", "web"]);
+        assert_eq!(
+            command.operations(),
+            [
+                Operation::Set(key!("tier"), label_value!("api")),
+                Operation::Set(key!("ex.io/team"), label_value!("")),
+                Operation::Remove(key!("zone")),
+            ]
+        );
+        assert!(command.overwrite());
+        assert!(!command.list());
+    }
+
+    #[rstest]
+    #[case(&["pods/web", "--list"], false, true)]
+    #[case(&["pods/web", "a=b", "--overwrite=false", "--list=true"], false, true)]
+    #[case(&["pods/web", "a=b", "--overwrite=T"], true, false)]
+    #[case(&["--overwrite", "--", "pods/web", "a=b"], true, false)]
+    fn test_parse_flags(#[case] args: &[&str], #[case] overwrite: bool, #[case] list: bool) {
+        let command = LabelCommand::parse(args).unwrap();
+        assert_eq!((command.overwrite(), command.list()), (overwrite, list));
+    }
+
+    #[rstest]
+    #[case(&["pods/web"], "at least one label update is required")]
+    #[case(&["pods", "a=b", "web"], "all resources must be specified before label changes: web")]
+    #[case(&["pods/web", "a=b", "a-"], "can not both modify and remove a label in the same command")]
+    #[case(&["pods/web", "--namespaces", "default", "a=b"], "unknown flag: --namespaces")]
+    #[case(&["pods/web", "-Ax", "a=b"], "unknown shorthand flag: 'x' in -Ax")]
+    #[case(&["pods/web", "a=b", "-n"], "flag needs an argument: 'n' in -n")]
+    #[case(&["pods/web", "a=b", "--selector"], "flag needs an argument: --selector")]
+    #[case(&["pods/web", "a=b", "--overwrite=yes"], "invalid argument \"yes\" for \"--overwrite\" flag")]
+    #[case(&["pods/web", "a=b", "--all=maybe"], "invalid argument \"maybe\" for \"--all\" flag")]
+    #[case(&["pods/web", "a=b c"], "argument 1: invalid character ' ' in value (at offset 3)")]
+    #[case(&["pods/web", "-n", "ns", "a=b c"], "argument 3: invalid character ' ' in value (at offset 3)")]
+    #[case(&["pods/web", "-n", "ns", "-A", "Ex.io/a-"], "argument 4: invalid character 'E' in key prefix (at offset 0)")]
+    fn test_parse_label_command_errors(#[case] args: &[&str], #[case] expected: &str) {
+        assert_eq!(LabelCommand::parse(args).unwrap_err().to_string(), expected);
+    }
+
+    #[rstest]
+    #[case(&["-n", "prod", "pods/web", "a=b", "--dry-run=server"])]
+    #[case(&["pods/web", "a=b", "--namespace=prod", "--dry-run", "--resource-version", "7"])]
+    #[case(&["pods", "-l", "app=web", "a=b", "--all-namespaces"])]
+    #[case(&["pods", "-lapp=web", "a=b", "-AR"])]
+    #[case(&["-f", "pod.yaml", "a=b", "--local", "-o=yaml", "--v", "6"])]
+    #[case(&["pods", "--all", "--field-selector", "status.phase=Running", "a=b"])]
+    fn test_kubectl_flags_skipped(#[case] args: &[&str]) {
+        let command = LabelCommand::parse(args).unwrap();
+        assert_eq!(
+            command.operations(),
+            [Operation::Set(key!("a"), label_value!("b"))]
+        );
+        assert!(command.resources().iter().all(|r| r.starts_with("pods")));
+        assert!(!command.overwrite());
+    }
+
+    #[test]
+    fn test_parse_command_strict() {
+        let args = ["pods/web".to_string(), format!("app={}", "a".repeat(64))];
+        let err = LabelCommand::parse(&args).unwrap_err();
+        assert_eq!((err.part(), err.offset()), (Some(Part::Value), Some(4)));
+        assert_eq!(
+            err,
+            Error::InvalidArgument {
+                index: 1,
+                error: Box::new(Error::ValueTooLong { len: 64, offset: 4 })
+            }
+        );
+        assert!(LabelCommand::parse_with_profile(&args, Profile::Lenient).is_ok());
+
+        let args = ["deploy/web", "Example.com/note=x"];
+        assert!(AnnotateCommand::parse(&args).is_err());
+        assert!(AnnotateCommand::parse_with_profile(&args, Profile::Lenient).is_ok());
+    }
+
+    #[test]
+    fn test_apply_label_command() {
+        let mut labels = labels! { "app" => "web", "tier" => "api", "zone" => "eu" };
+        LabelCommand::parse(&["pods/web", "app=web", "team=core", "zone-", "gone-"])
+            .unwrap()
+            .apply_to(&mut labels)
+            .unwrap();
+        assert_eq!(
+            labels,
+            labels! { "app" => "web", "tier" => "api", "team" => "core" }
+        );
+    }
+
+    #[test]
+    fn test_apply_label_command_overwrite() {
+        let mut labels = labels! { "app" => "web", "tier" => "api" };
+        let args = ["pods/web", "tier=db", "app=db", "app=web", "zone=eu"];
+        let err = LabelCommand::parse(&args)
+            .unwrap()
+            .apply_to(&mut labels)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'tier' already has a value (api), and --overwrite is false"
+        );
+        assert_eq!(labels, labels! { "app" => "web", "tier" => "api" });
+
+        let err = LabelCommand::parse(&["pods/web", "tier=db", "app=db"])
+            .unwrap()
+            .apply_to(&mut labels)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            Error::WouldOverwriteLabels(vec![
+                (key!("app"), "web".to_string()),
+                (key!("tier"), "api".to_string()),
+            ])
+        );
+
+        LabelCommand::parse(&["pods/web", "tier=db", "--overwrite"])
+            .unwrap()
+            .apply_to(&mut labels)
+            .unwrap();
+        assert_eq!(labels, labels! { "app" => "web", "tier" => "db" });
+    }
+
+    #[test]
+    fn test_annotate_command() {
+        let command = AnnotateCommand::parse(&[
+            "deploy/web",
+            "description=a web server, or two",
+            "kubernetes.io/change-cause=kubectl apply",
+            "note=new",
+        ])
+        .unwrap();
+        let mut current = annotations(&[
+            ("kubernetes.io/change-cause", "kubectl create"),
+            ("note", "old"),
+        ]);
+        assert_eq!(
+            command.apply_to(&mut current).unwrap_err().to_string(),
+            "--overwrite is false but found the following declared annotation(s): 'note' already has a value (old)"
+        );
+        current.remove(&key!("note"));
+        command.apply_to(&mut current).unwrap();
+        assert_eq!(
+            current,
+            annotations(&[
+                ("kubernetes.io/change-cause", "kubectl apply"),
+                ("description", "a web server, or two"),
+                ("note", "new"),
+            ])
+        );
+        assert_eq!(
+            AnnotateCommand::parse(&["deploy/web", "note=x", "note-"])
+                .unwrap_err()
+                .to_string(),
+            "can not both modify and remove an annotation in the same command"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Support for the `key!`, `label_value!` and `labels!` macros
+//!
+//! The checks are `const fn`s applying the rules of `Profile::Lenient`, the
+//! profile `parse_str` uses, so that the macros reject exactly the literals
+//! `parse_str` would and do so while compiling.
+
+use alloc::string::ToString;
+
+use crate::types::{Key, KeyName, KeyPrefix, LabelValue};
+use crate::validate::{MAX_DNS_LABEL_LEN, MAX_NAME_LEN, MAX_PREFIX_LEN, MIN_DNS_LABEL_LEN};
+
+const fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.'
+}
+
+const fn is_dns_label_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-'
+}
+
+/// Check the non empty `bytes[start..end]` only holds characters allowed in
+/// a DNS label or a name and starts and ends with an alphanumeric character
+const fn check_part(
+    bytes: &[u8],
+    start: usize,
+    end: usize,
+    dns_label: bool,
+) -> Result<(), &'static str> {
+    let mut i = start;
+    while i < end {
+        let allowed = if dns_label {
+            is_dns_label_byte(bytes[i])
+        } else {
+            is_name_byte(bytes[i])
+        };
+        if !allowed {
+            return Err("invalid character");
+        }
+        i += 1;
+    }
+    if !bytes[start].is_ascii_alphanumeric() {
+        return Err("must start with an alphanumeric character");
+    }
+    if !bytes[end - 1].is_ascii_alphanumeric() {
+        return Err("must end with an alphanumeric character");
+    }
+    Ok(())
+}
+
+const fn check_prefix(bytes: &[u8], end: usize) -> Result<(), &'static str> {
+    if end > MAX_PREFIX_LEN {
+        return Err("key prefix is too long");
+    }
+    let mut start = 0;
+    let mut i = 0;
+    while i <= end {
+        if i == end || bytes[i] == b'.' {
+            if i == start {
+                return Err("key prefix must not contain an empty DNS label");
+            }
+            if let Err(err) = check_part(bytes, start, i, true) {
+                return Err(err);
+            }
+            if i - start > MAX_DNS_LABEL_LEN {
+                return Err("DNS label in key prefix is too long");
+            }
+            if i - start < MIN_DNS_LABEL_LEN {
+                return Err("DNS label in key prefix is too short");
+            }
+            start = i + 1;
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Why `input` is not a valid key, if it is not
+pub const fn check_key(input: &str) -> Result<(), &'static str> {
+    let bytes = input.as_bytes();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'/' {
+            if let Err(err) = check_prefix(bytes, i) {
+                return Err(err);
+            }
+            start = i + 1;
+            break;
+        }
+        i += 1;
+    }
+    let end = bytes.len();
+    if start == end {
+        return Err("key name must not be empty");
+    }
+    if let Err(err) = check_part(bytes, start, end, false) {
+        return Err(err);
+    }
+    if end - start > MAX_NAME_LEN {
+        return Err("key name is too long");
+    }
+    Ok(())
+}
+
+/// Why `input` is not a valid label value, if it is not
+pub const fn check_value(input: &str) -> Result<(), &'static str> {
+    let bytes = input.as_bytes();
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    check_part(bytes, 0, bytes.len(), false)
+}
+
+pub const fn assert_key(input: &str) {
+    if let Err(err) = check_key(input) {
+        panic!("{}", err);
+    }
+}
+
+pub const fn assert_value(input: &str) {
+    if let Err(err) = check_value(input) {
+        panic!("{}", err);
+    }
+}
+
+/// Build a key checked by `assert_key`
+pub fn key_unchecked(input: &str) -> Key {
+    match input.find('/') {
+        Some(i) => Key::new_with_prefix(
+            KeyPrefix(input[..i].to_string()),
+            KeyName(input[i + 1..].to_string()),
+        ),
+        None => Key::new_no_prefix(KeyName(input.to_string())),
+    }
+}
+
+/// Build a label value checked by `assert_value`
+pub fn value_unchecked(input: &str) -> LabelValue {
+    LabelValue(input.to_string())
+}
+
+/// A `Key` checked while compiling
+///
+/// ```
+/// let key = klap::key!("app.kubernetes.io/name");
+/// assert_eq!(key.prefix(), Some("app.kubernetes.io"));
+/// ```
+///
+/// ```compile_fail
+/// let key = klap::key!("app.kubernetes.io/-name");
+/// ```
+#[macro_export]
+macro_rules! key {
+    ($key:expr) => {{
+        const _: () = $crate::literal::assert_key($key);
+        $crate::literal::key_unchecked($key)
+    }};
+}
+
+/// A `LabelValue` checked while compiling
+///
+/// ```
+/// let value = klap::label_value!("prod");
+/// assert_eq!(value.as_str(), "prod");
+/// ```
+///
+/// ```compile_fail
+/// let value = klap::label_value!("prod env");
+/// ```
+#[macro_export]
+macro_rules! label_value {
+    ($value:expr) => {{
+        const _: () = $crate::literal::assert_value($value);
+        $crate::literal::value_unchecked($value)
+    }};
+}
+
+/// A `LabelMap` of keys and values checked while compiling
+///
+/// ```
+/// let labels = klap::labels! { "app" => "web", "tier" => "api" };
+/// assert_eq!(labels[&klap::key!("tier")].as_str(), "api");
+/// ```
+///
+/// ```compile_fail
+/// let labels = klap::labels! { "app" => "web", "tier" => "api/" };
+/// ```
+#[cfg(feature = "std")]
+#[macro_export]
+macro_rules! labels {
+    ($($key:expr => $value:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut labels = $crate::LabelMap::new();
+        $(labels.insert($crate::key!($key), $crate::label_value!($value));)*
+        labels
+    }};
+}
@@ -0,0 +1,303 @@
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+use alloc::string::String;
+#[cfg(feature = "serde_support")]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use crate::field::{FieldAccessor, FieldPath};
+use crate::parser::label_keyprefix_from_str_with_profile;
+use crate::types::*;
+
+/// The operator of a node selector requirement
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum NodeSelectorOperator {
+    In,
+    NotIn,
+    Exists,
+    DoesNotExist,
+    /// The label value, read as an integer, is greater than the given one
+    Gt,
+    /// The label value, read as an integer, is less than the given one
+    Lt,
+}
+
+#[cfg(feature = "serde_support")]
+#[derive(Deserialize)]
+struct RawNodeSelectorRequirement {
+    key: Key,
+    operator: NodeSelectorOperator,
+    #[serde(default)]
+    values: Vec<LabelValue>,
+}
+
+/// A single node affinity requirement, e.g. `{key: cpus, operator: Gt, values: ["4"]}`
+#[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde_support",
+    serde(try_from = "RawNodeSelectorRequirement")
+)]
+pub struct NodeSelectorRequirement {
+    pub key: Key,
+    pub operator: NodeSelectorOperator,
+    #[cfg_attr(
+        feature = "serde_support",
+        serde(skip_serializing_if = "Vec::is_empty")
+    )]
+    pub values: Vec<LabelValue>,
+}
+
+impl NodeSelectorRequirement {
+    pub fn new(
+        key: Key,
+        operator: NodeSelectorOperator,
+        values: Vec<LabelValue>,
+    ) -> Result<Self, Error> {
+        match operator {
+            NodeSelectorOperator::In | NodeSelectorOperator::NotIn if values.is_empty() => Err(
+                Error::InvalidRequirement("values must be non-empty for In and NotIn"),
+            ),
+            NodeSelectorOperator::Exists | NodeSelectorOperator::DoesNotExist
+                if !values.is_empty() =>
+            {
+                Err(Error::InvalidRequirement(
+                    "values must be empty for Exists and DoesNotExist",
+                ))
+            }
+            NodeSelectorOperator::Gt | NodeSelectorOperator::Lt
+                if values.len() != 1 || parse_int(&values[0]).is_none() =>
+            {
+                Err(Error::InvalidRequirement(
+                    "values must be a single integer for Gt and Lt",
+                ))
+            }
+            _ => Ok(NodeSelectorRequirement {
+                key,
+                operator,
+                values,
+            }),
+        }
+    }
+
+    /// Check whether the requirement holds for the given node labels
+    #[cfg(feature = "std")]
+    pub fn matches(&self, labels: &LabelMap) -> bool {
+        let label = labels.get(&self.key);
+        match self.operator {
+            NodeSelectorOperator::In => match label {
+                Some(value) => self.values.contains(value),
+                None => false,
+            },
+            NodeSelectorOperator::NotIn => match label {
+                Some(value) => !self.values.contains(value),
+                None => true,
+            },
+            NodeSelectorOperator::Exists => label.is_some(),
+            NodeSelectorOperator::DoesNotExist => label.is_none(),
+            NodeSelectorOperator::Gt | NodeSelectorOperator::Lt => {
+                let (label, bound) = match (
+                    label.and_then(parse_int),
+                    self.values.first().and_then(parse_int),
+                ) {
+                    (Some(label), Some(bound)) => (label, bound),
+                    _ => return false,
+                };
+                match self.operator {
+                    NodeSelectorOperator::Gt => label > bound,
+                    _ => label < bound,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde_support")]
+impl core::convert::TryFrom<RawNodeSelectorRequirement> for NodeSelectorRequirement {
+    type Error = Error;
+    fn try_from(raw: RawNodeSelectorRequirement) -> Result<Self, Self::Error> {
+        NodeSelectorRequirement::new(raw.key, raw.operator, raw.values)
+    }
+}
+
+fn parse_int(value: &LabelValue) -> Option<i64> {
+    value.as_str().parse().ok()
+}
+
+#[cfg(feature = "serde_support")]
+#[derive(Serialize, Deserialize)]
+struct RawNodeSelectorFieldRequirement {
+    key: String,
+    operator: NodeSelectorOperator,
+    values: Vec<String>,
+}
+
+/// A node affinity requirement on a field of the node, e.g.
+/// `{key: metadata.name, operator: In, values: [node-1]}`
+///
+/// As kubernetes validates them, the operator is `In` or `NotIn` and there is
+/// a single value, a node name.
+#[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde_support",
+    serde(
+        try_from = "RawNodeSelectorFieldRequirement",
+        into = "RawNodeSelectorFieldRequirement"
+    )
+)]
+pub struct NodeSelectorFieldRequirement {
+    pub key: FieldPath,
+    pub operator: NodeSelectorOperator,
+    pub values: Vec<String>,
+}
+
+impl NodeSelectorFieldRequirement {
+    pub fn new(
+        key: FieldPath,
+        operator: NodeSelectorOperator,
+        values: Vec<String>,
+    ) -> Result<Self, Error> {
+        match operator {
+            NodeSelectorOperator::In | NodeSelectorOperator::NotIn => (),
+            _ => {
+                return Err(Error::InvalidRequirement(
+                    "operator must be In or NotIn for fields",
+                ))
+            }
+        }
+        if values.len() != 1 {
+            return Err(Error::InvalidRequirement(
+                "values must be a single node name for fields",
+            ));
+        }
+        // node names are lowercase DNS subdomains, as strict key prefixes
+        if label_keyprefix_from_str_with_profile(&values[0], Profile::Strict).is_err() {
+            return Err(Error::InvalidRequirement(
+                "values must be a DNS subdomain for fields",
+            ));
+        }
+        Ok(NodeSelectorFieldRequirement {
+            key,
+            operator,
+            values,
+        })
+    }
+
+    /// Check whether the requirement holds for the given node fields, a
+    /// missing field being empty
+    pub fn matches<A: FieldAccessor + ?Sized>(&self, fields: &A) -> bool {
+        let field = fields.field(&self.key);
+        let found = self
+            .values
+            .iter()
+            .any(|value| *value == field.as_deref().unwrap_or(""));
+        match self.operator {
+            NodeSelectorOperator::NotIn => !found,
+            _ => found,
+        }
+    }
+}
+
+#[cfg(feature = "serde_support")]
+impl core::convert::TryFrom<RawNodeSelectorFieldRequirement> for NodeSelectorFieldRequirement {
+    type Error = Error;
+    fn try_from(raw: RawNodeSelectorFieldRequirement) -> Result<Self, Self::Error> {
+        NodeSelectorFieldRequirement::new(FieldPath::parse_str(&raw.key)?, raw.operator, raw.values)
+    }
+}
+
+#[cfg(feature = "serde_support")]
+impl From<NodeSelectorFieldRequirement> for RawNodeSelectorFieldRequirement {
+    fn from(req: NodeSelectorFieldRequirement) -> Self {
+        RawNodeSelectorFieldRequirement {
+            key: req.key.to_string(),
+            operator: req.operator,
+            values: req.values,
+        }
+    }
+}
+
+/// The AND of its requirements
+///
+/// `match_fields` holds requirements on the fields of the node rather than
+/// its labels, e.g. `metadata.name`, as DaemonSets use to pin their pods. As
+/// in kubernetes a term without requirements matches no node.
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Default)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde_support", serde(rename_all = "camelCase"))]
+pub struct NodeSelectorTerm {
+    #[cfg_attr(
+        feature = "serde_support",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub match_expressions: Vec<NodeSelectorRequirement>,
+    #[cfg_attr(
+        feature = "serde_support",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub match_fields: Vec<NodeSelectorFieldRequirement>,
+}
+
+impl NodeSelectorTerm {
+    pub fn new(match_expressions: Vec<NodeSelectorRequirement>) -> Self {
+        NodeSelectorTerm {
+            match_expressions,
+            match_fields: Vec::new(),
+        }
+    }
+    /// Check whether every requirement holds for the given node labels, the
+    /// node having no fields
+    #[cfg(feature = "std")]
+    pub fn matches(&self, labels: &LabelMap) -> bool {
+        self.matches_with_fields(labels, &HashMap::<String, String>::new())
+    }
+    /// Check whether every requirement holds for the given node labels and
+    /// fields, e.g. `metadata.name` giving the name of the node
+    #[cfg(feature = "std")]
+    pub fn matches_with_fields<A: FieldAccessor + ?Sized>(
+        &self,
+        labels: &LabelMap,
+        fields: &A,
+    ) -> bool {
+        !(self.match_expressions.is_empty() && self.match_fields.is_empty())
+            && self.match_expressions.iter().all(|req| req.matches(labels))
+            && self.match_fields.iter().all(|req| req.matches(fields))
+    }
+}
+
+/// A node affinity selector, the OR of its terms
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Default)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde_support", serde(rename_all = "camelCase"))]
+pub struct NodeSelector {
+    pub node_selector_terms: Vec<NodeSelectorTerm>,
+}
+
+impl NodeSelector {
+    pub fn new(node_selector_terms: Vec<NodeSelectorTerm>) -> Self {
+        NodeSelector {
+            node_selector_terms,
+        }
+    }
+    /// Check whether any term matches the given node labels
+    #[cfg(feature = "std")]
+    pub fn matches(&self, labels: &LabelMap) -> bool {
+        self.matches_with_fields(labels, &HashMap::<String, String>::new())
+    }
+    /// Check whether any term matches the given node labels and fields
+    #[cfg(feature = "std")]
+    pub fn matches_with_fields<A: FieldAccessor + ?Sized>(
+        &self,
+        labels: &LabelMap,
+        fields: &A,
+    ) -> bool {
+        self.node_selector_terms
+            .iter()
+            .any(|term| term.matches_with_fields(labels, fields))
+    }
+}
@@ -1,13 +1,201 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::Range;
 use pest::Parser;
 use pest_derive::*;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
 
+use crate::borrowed::*;
+use crate::field::*;
+use crate::selector::*;
 use crate::types::*;
+use crate::validate::*;
 
 #[derive(Parser)]
 #[grammar = "labels.pest"]
 struct LabelParser;
 
-fn match_key(part: pest::iterators::Pair<'_, Rule>) -> Key {
+/// How rules are named in syntax errors, so grammar internals don't leak
+fn rule_name(rule: &Rule) -> String {
+    match rule {
+        Rule::EOI => "end of input",
+        Rule::alpha | Rule::digit | Rule::alphanumeric => "an alphanumeric character",
+        Rule::whitespace => "whitespace",
+        Rule::dns_label_chars | Rule::subdomain_chars | Rule::dns_label | Rule::dns_subdomain => {
+            "a key prefix"
+        }
+        Rule::label_name_inner | Rule::label_part | Rule::label_key_name => "a key name",
+        Rule::label_key => "a key",
+        Rule::label_value | Rule::selector_value => "a value",
+        Rule::label | Rule::label_colon_spec => "a label",
+        Rule::annotation_value | Rule::annotation_quoted_value | Rule::annotation_plain_value => {
+            "an annotation value"
+        }
+        Rule::annotation_list_entry => "an annotation",
+        Rule::labels_file_label => "a label",
+        Rule::labels_file_include => "`@include`",
+        Rule::labels_file_include_path => "a file to include",
+        Rule::selector_op => "an operator",
+        Rule::selector_set_op => "`in` or `notin`",
+        Rule::selector_not_exists => "`!`",
+        Rule::selector_requirement => "a selector requirement",
+        Rule::field_path_segment | Rule::field_path => "a field path",
+        Rule::field_value => "a field value",
+        Rule::field_requirement => "a field selector requirement",
+        _ => "valid input",
+    }
+    .to_string()
+}
+
+impl From<pest::error::Error<Rule>> for Error {
+    fn from(err: pest::error::Error<Rule>) -> Self {
+        let offset = match err.location {
+            pest::error::InputLocation::Pos(pos) => pos,
+            pest::error::InputLocation::Span((start, _)) => start,
+        };
+        let err = err.renamed_rules(rule_name);
+        Error::Syntax {
+            offset,
+            message: err.variant.message().into_owned(),
+        }
+    }
+}
+
+/// Replace a syntax error by the typed error `check` finds at its offset
+fn explain<F>(err: pest::error::Error<Rule>, check: F) -> Error
+where
+    F: FnOnce(usize) -> Result<(), Error>,
+{
+    match Error::from(err) {
+        Error::Syntax { offset, message } => match check(offset) {
+            Err(err) => err,
+            Ok(()) => Error::Syntax { offset, message },
+        },
+        err => err,
+    }
+}
+
+fn is_list_separator(c: char) -> bool {
+    c == ',' || c.is_ascii_whitespace()
+}
+
+fn is_selector_separator(c: char) -> bool {
+    is_list_separator(c) || c == '(' || c == ')'
+}
+
+/// The entry of a list around `offset` and where it starts
+fn entry_at(input: &str, offset: usize, separator: fn(char) -> bool) -> Option<(usize, &str)> {
+    let start = input[..offset].rfind(separator).map_or(0, |i| i + 1);
+    let end = input[offset..]
+        .find(separator)
+        .map_or(input.len(), |i| offset + i);
+    if start < end {
+        Some((start, &input[start..end]))
+    } else {
+        None
+    }
+}
+
+fn check_list_entry(
+    input: &str,
+    offset: usize,
+    sep: &[char],
+    profile: Profile,
+) -> Result<(), Error> {
+    match entry_at(input, offset, is_list_separator) {
+        Some((start, entry)) => check_entry(entry, start, sep, profile),
+        None => Ok(()),
+    }
+}
+
+fn in_value_set(before: &str) -> bool {
+    before.matches('(').count() > before.matches(')').count()
+}
+
+fn check_selector_word(input: &str, offset: usize, profile: Profile) -> Result<(), Error> {
+    let (start, word) = match entry_at(input, offset, is_selector_separator) {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+    let before = &input[..start];
+    if in_value_set(before) || before.trim_end().ends_with('=') {
+        return check_value(word, start, profile);
+    }
+    match word.find('=') {
+        Some(i) => {
+            let key_end = if word[..i].ends_with('!') { i - 1 } else { i };
+            let value_start = if word[i + 1..].starts_with('=') {
+                i + 2
+            } else {
+                i + 1
+            };
+            check_key(&word[..key_end], start, profile)?;
+            check_value(&word[value_start..], start + value_start, profile)
+        }
+        None => match word.strip_prefix('!') {
+            Some(key) => check_key(key, start + 1, profile),
+            None => check_key(word, start, profile),
+        },
+    }
+}
+
+/// Check the words of the requirement from `offset` on, as the grammar may
+/// report a failing requirement at its start rather than at the bad word
+fn check_selector_requirement(input: &str, offset: usize, profile: Profile) -> Result<(), Error> {
+    let mut pos = offset;
+    loop {
+        check_selector_word(input, pos, profile)?;
+        let mut next = None;
+        for (i, c) in input[pos..]
+            .char_indices()
+            .skip_while(|&(_, c)| !is_selector_separator(c))
+        {
+            if c == ',' && !in_value_set(&input[..pos + i]) {
+                return Ok(());
+            }
+            if !is_selector_separator(c) {
+                next = Some(pos + i);
+                break;
+            }
+        }
+        match next {
+            Some(next) => pos = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+/// The grammar accepts the prefixes of every profile, the profile specific
+/// rules are checked here
+fn match_prefix<'i>(
+    part: pest::iterators::Pair<'i, Rule>,
+    profile: Profile,
+) -> Result<&'i str, Error> {
+    check_prefix(part.as_str(), part.as_span().start(), profile)?;
+    Ok(part.as_str())
+}
+
+fn match_value_ref<'i>(
+    part: pest::iterators::Pair<'i, Rule>,
+    profile: Profile,
+) -> Result<LabelValueRef<'i>, Error> {
+    check_value(part.as_str(), part.as_span().start(), profile)?;
+    Ok(LabelValueRef(part.as_str()))
+}
+
+fn match_value(
+    part: pest::iterators::Pair<'_, Rule>,
+    profile: Profile,
+) -> Result<LabelValue, Error> {
+    match_value_ref(part, profile).map(|value| value.to_owned())
+}
+
+fn match_key_ref<'i>(
+    part: pest::iterators::Pair<'i, Rule>,
+    profile: Profile,
+) -> Result<KeyRef<'i>, Error> {
     match part.as_rule() {
         Rule::label_key => {
             let mut pairs = part.into_inner();
@@ -16,89 +204,346 @@ fn match_key(part: pest::iterators::Pair<'_, Rule>) -> Key {
                 Rule::dns_subdomain => {
                     let second = pairs.next().unwrap();
                     match second.as_rule() {
-                        Rule::label_key_name => Key::new_with_prefix(
-                            KeyPrefix(first.as_str().to_string()),
-                            KeyName(second.as_str().to_string()),
-                        ),
+                        Rule::label_key_name => {
+                            KeyRef::new(Some(match_prefix(first, profile)?), second.as_str())
+                        }
                         _ => unreachable!(),
                     }
                 }
-                Rule::label_key_name => Key::new_no_prefix(KeyName(first.as_str().to_string())),
+                Rule::label_key_name => KeyRef::new(None, first.as_str()),
                 _ => unreachable!(),
             };
             assert!(pairs.next().is_none());
-            key
+            Ok(key)
         }
         _ => panic!("called with non-key rule"),
     }
 }
 
-fn match_label(part: pest::iterators::Pair<'_, Rule>) -> Label {
+fn match_key(part: pest::iterators::Pair<'_, Rule>, profile: Profile) -> Result<Key, Error> {
+    match_key_ref(part, profile).map(|key| key.to_owned())
+}
+
+fn match_label_ref<'i>(
+    part: pest::iterators::Pair<'i, Rule>,
+    profile: Profile,
+) -> Result<LabelRef<'i>, Error> {
     let mut i = part.into_inner();
-    let key = match_key(i.next().unwrap());
-    let value = i.next().map(|v| v.as_str()).unwrap_or("");
+    let key = match_key_ref(i.next().unwrap(), profile)?;
+    let value = match i.next() {
+        Some(value) => match_value_ref(value, profile)?,
+        None => LabelValueRef(""),
+    };
     assert!(i.next().is_none());
-    Label::new(key, LabelValue(value.to_string()))
+    Ok(LabelRef::new(key, value))
 }
 
-pub fn label_keyprefix_from_str(input: &str) -> Result<KeyPrefix, Error> {
-    let mut pairs = LabelParser::parse(Rule::label_keyprefix_whole, input)?;
-    let first = pairs.next().unwrap();
-    let prefix = match first.as_rule() {
-        Rule::dns_subdomain => KeyPrefix(first.as_str().to_string()),
-        _ => unreachable!(),
-    };
-    match pairs.next().unwrap().as_rule() {
-        Rule::EOI => Ok(prefix),
-        _ => unreachable!(),
+/// Parse a list of labels, each matched by `label_rule` and separated from
+/// its value by one of `sep`
+fn parse_labels<'i, T>(
+    rule: Rule,
+    label_rule: Rule,
+    sep: &[char],
+    input: &'i str,
+    profile: Profile,
+    convert: fn(LabelRef<'i>) -> T,
+) -> Result<Vec<T>, Error> {
+    let labels = parse_label_spans(rule, label_rule, sep, input, profile)?;
+    Ok(labels
+        .into_iter()
+        .map(|(_, label)| convert(label))
+        .collect())
+}
+
+/// Parse a list of labels as `parse_labels`, along with the span of each key
+fn parse_label_spans<'i>(
+    rule: Rule,
+    label_rule: Rule,
+    sep: &[char],
+    input: &'i str,
+    profile: Profile,
+) -> Result<Vec<(Range<usize>, LabelRef<'i>)>, Error> {
+    let mut res = Vec::new();
+    for pair in LabelParser::parse(rule, input)
+        .map_err(|e| explain(e, |offset| check_list_entry(input, offset, sep, profile)))?
+    {
+        match pair.as_rule() {
+            r if r == label_rule => {
+                let key = pair.clone().into_inner().next().unwrap().as_span();
+                res.push((key.start()..key.end(), match_label_ref(pair, profile)?));
+            }
+            Rule::EOI => (),
+            _ => unreachable!(),
+        }
     }
+    Ok(res)
 }
 
-pub fn label_keyname_from_str(input: &str) -> Result<KeyName, Error> {
-    let mut pairs = LabelParser::parse(Rule::label_keyname_whole, input)?;
-    let first = pairs.next().unwrap();
-    let name = match first.as_rule() {
-        Rule::label_key_name => KeyName(first.as_str().to_string()),
-        _ => unreachable!(),
-    };
-    match pairs.next().unwrap().as_rule() {
-        Rule::EOI => Ok(name),
-        _ => unreachable!(),
+/// Apply `policy` to the keys given more than once, returning the labels
+/// left and every conflict found
+fn resolve_conflicts<L: Into<Label>>(
+    labels: Vec<(Range<usize>, L)>,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Label>, Vec<Conflict>), Error> {
+    let mut res: Vec<Label> = Vec::with_capacity(labels.len());
+    let mut conflicts = Vec::new();
+    // the index in `res` and the span of the first occurrence of every key,
+    // and the span of the first key of every lowercased form
+    let mut seen: BTreeMap<String, (usize, Range<usize>)> = BTreeMap::new();
+    let mut seen_lowercase: BTreeMap<String, Range<usize>> = BTreeMap::new();
+    for (span, label) in labels {
+        let label: Label = label.into();
+        let key = label.key.to_string();
+        if let Some((index, first)) = seen.get(&key) {
+            let conflict = Conflict {
+                kind: ConflictKind::Duplicate,
+                first: first.clone(),
+                second: span,
+            };
+            match policy {
+                ConflictPolicy::Error => return Err(conflict.to_error()),
+                ConflictPolicy::FirstWins => (),
+                ConflictPolicy::LastWins => res[*index].value = label.value,
+            }
+            conflicts.push(conflict);
+            continue;
+        }
+        let lowercase = key.to_ascii_lowercase();
+        if let Some(first) = seen_lowercase.get(&lowercase) {
+            let conflict = Conflict {
+                kind: ConflictKind::CaseCollision,
+                first: first.clone(),
+                second: span.clone(),
+            };
+            if policy == ConflictPolicy::Error {
+                return Err(conflict.to_error());
+            }
+            conflicts.push(conflict);
+        } else {
+            seen_lowercase.insert(lowercase, span.clone());
+        }
+        seen.insert(key, (res.len(), span));
+        res.push(label);
     }
+    Ok((res, conflicts))
 }
 
-pub fn label_key_from_str(input: &str) -> Result<Key, Error> {
-    let mut pairs = LabelParser::parse(Rule::label_key_whole, input)?;
-    let first = pairs.next().unwrap();
-    let key = match first.as_rule() {
-        Rule::label_key => match_key(first),
-        _ => unreachable!(),
-    };
-    match pairs.next().unwrap().as_rule() {
-        Rule::EOI => Ok(key),
-        _ => unreachable!(),
+/// Parsers for single keys and values running the grammar, the default
+/// backend
+#[cfg(any(test, not(feature = "fast_validator")))]
+pub(crate) mod pest_backend {
+    use pest::Parser;
+
+    use super::*;
+
+    pub(crate) fn prefix(input: &str, profile: Profile) -> Result<&str, Error> {
+        let mut pairs = LabelParser::parse(Rule::label_keyprefix_whole, input)
+            .map_err(|e| explain(e, |_| check_prefix(input, 0, profile)))?;
+        let first = pairs.next().unwrap();
+        let prefix = match first.as_rule() {
+            Rule::dns_subdomain => match_prefix(first, profile)?,
+            _ => unreachable!(),
+        };
+        match pairs.next().unwrap().as_rule() {
+            Rule::EOI => Ok(prefix),
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn name(input: &str, profile: Profile) -> Result<&str, Error> {
+        let mut pairs = LabelParser::parse(Rule::label_keyname_whole, input)
+            .map_err(|e| explain(e, |_| check_name(input, 0)))?;
+        let first = pairs.next().unwrap();
+        let name = match first.as_rule() {
+            Rule::label_key_name => first.as_str(),
+            _ => unreachable!(),
+        };
+        // the grammar limits names only within keys
+        if profile == Profile::Strict && name.len() > MAX_NAME_LEN {
+            return Err(Error::KeyNameTooLong {
+                len: name.len(),
+                offset: 0,
+            });
+        }
+        match pairs.next().unwrap().as_rule() {
+            Rule::EOI => Ok(name),
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn key(input: &str, profile: Profile) -> Result<KeyRef<'_>, Error> {
+        let mut pairs = LabelParser::parse(Rule::label_key_whole, input)
+            .map_err(|e| explain(e, |_| check_key(input, 0, profile)))?;
+        let first = pairs.next().unwrap();
+        let key = match first.as_rule() {
+            Rule::label_key => match_key_ref(first, profile)?,
+            _ => unreachable!(),
+        };
+        match pairs.next().unwrap().as_rule() {
+            Rule::EOI => Ok(key),
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn value(input: &str, profile: Profile) -> Result<LabelValueRef<'_>, Error> {
+        let mut pairs = LabelParser::parse(Rule::label_value_whole, input)
+            .map_err(|e| explain(e, |_| check_value(input, 0, profile)))?;
+        let first = pairs.next().unwrap();
+        let value = match first.as_rule() {
+            Rule::label_value => match_value_ref(first, profile)?,
+            Rule::EOI => return Ok(LabelValueRef("")),
+            _ => unreachable!(),
+        };
+        match pairs.next().unwrap().as_rule() {
+            Rule::EOI => Ok(value),
+            _ => unreachable!(),
+        }
     }
 }
 
+#[cfg(not(feature = "fast_validator"))]
+use self::pest_backend as backend;
+#[cfg(feature = "fast_validator")]
+use crate::scan as backend;
+
+pub fn label_keyprefix_from_str(input: &str) -> Result<KeyPrefix, Error> {
+    label_keyprefix_from_str_with_profile(input, Profile::default())
+}
+
+pub fn label_keyprefix_from_str_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<KeyPrefix, Error> {
+    backend::prefix(input, profile).map(|prefix| KeyPrefix(prefix.to_string()))
+}
+
+pub fn label_keyname_from_str(input: &str) -> Result<KeyName, Error> {
+    label_keyname_from_str_with_profile(input, Profile::default())
+}
+
+/// Key names on their own are only limited in length by `Profile::Strict`,
+/// as in keys
+pub fn label_keyname_from_str_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<KeyName, Error> {
+    backend::name(input, profile).map(|name| KeyName(name.to_string()))
+}
+
+pub fn label_key_from_str(input: &str) -> Result<Key, Error> {
+    label_key_from_str_with_profile(input, Profile::default())
+}
+
+pub fn label_key_from_str_with_profile(input: &str, profile: Profile) -> Result<Key, Error> {
+    label_key_ref_from_str_with_profile(input, profile).map(|key| key.to_owned())
+}
+
+pub fn label_key_ref_from_str(input: &str) -> Result<KeyRef<'_>, Error> {
+    label_key_ref_from_str_with_profile(input, Profile::default())
+}
+
+pub fn label_key_ref_from_str_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<KeyRef<'_>, Error> {
+    backend::key(input, profile)
+}
+
 pub fn label_value_from_str(input: &str) -> Result<LabelValue, Error> {
-    let mut pairs = LabelParser::parse(Rule::label_value_whole, input)?;
+    label_value_from_str_with_profile(input, Profile::default())
+}
+
+pub fn label_value_from_str_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<LabelValue, Error> {
+    label_value_ref_from_str_with_profile(input, profile).map(|value| value.to_owned())
+}
+
+pub fn label_value_ref_from_str(input: &str) -> Result<LabelValueRef<'_>, Error> {
+    label_value_ref_from_str_with_profile(input, Profile::default())
+}
+
+pub fn label_value_ref_from_str_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<LabelValueRef<'_>, Error> {
+    backend::value(input, profile)
+}
+
+pub fn label_from_envstr(input: &str) -> Result<Label, Error> {
+    label_from_envstr_with_profile(input, Profile::default())
+}
+
+pub fn label_from_envstr_with_profile(input: &str, profile: Profile) -> Result<Label, Error> {
+    label_ref_from_envstr_with_profile(input, profile).map(|label| label.to_owned())
+}
+
+pub fn label_ref_from_envstr(input: &str) -> Result<LabelRef<'_>, Error> {
+    label_ref_from_envstr_with_profile(input, Profile::default())
+}
+
+pub fn label_ref_from_envstr_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<LabelRef<'_>, Error> {
+    let mut pairs = LabelParser::parse(Rule::label_whole, input)
+        .map_err(|e| explain(e, |_| check_entry(input, 0, &['='], profile)))?;
     let first = pairs.next().unwrap();
-    let value = match first.as_rule() {
-        Rule::label_value => LabelValue(first.as_str().to_string()),
-        Rule::EOI => return Ok(LabelValue("".to_string())),
+    let label = match first.as_rule() {
+        Rule::label => match_label_ref(first, profile)?,
         _ => unreachable!(),
     };
     match pairs.next().unwrap().as_rule() {
-        Rule::EOI => Ok(value),
+        Rule::EOI => Ok(label),
         _ => unreachable!(),
     }
 }
 
-pub fn label_from_envstr(input: &str) -> Result<Label, Error> {
-    let mut pairs = LabelParser::parse(Rule::label_whole, input)?;
+pub fn labels_from_envstr(input: &str) -> Result<Vec<Label>, Error> {
+    labels_from_envstr_with_profile(input, Profile::default())
+}
+
+pub fn labels_from_envstr_with_profile(input: &str, profile: Profile) -> Result<Vec<Label>, Error> {
+    parse_labels(Rule::labels, Rule::label, &['='], input, profile, |label| {
+        label.to_owned()
+    })
+}
+
+pub fn labels_ref_from_envstr(input: &str) -> Result<Vec<LabelRef<'_>>, Error> {
+    labels_ref_from_envstr_with_profile(input, Profile::default())
+}
+
+pub fn labels_ref_from_envstr_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<Vec<LabelRef<'_>>, Error> {
+    parse_labels(Rule::labels, Rule::label, &['='], input, profile, |label| {
+        label
+    })
+}
+
+pub fn label_from_str_wcolon(input: &str) -> Result<Label, Error> {
+    label_from_str_wcolon_with_profile(input, Profile::default())
+}
+
+pub fn label_from_str_wcolon_with_profile(input: &str, profile: Profile) -> Result<Label, Error> {
+    label_ref_from_str_wcolon_with_profile(input, profile).map(|label| label.to_owned())
+}
+
+pub fn label_ref_from_str_wcolon(input: &str) -> Result<LabelRef<'_>, Error> {
+    label_ref_from_str_wcolon_with_profile(input, Profile::default())
+}
+
+pub fn label_ref_from_str_wcolon_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<LabelRef<'_>, Error> {
+    let mut pairs = LabelParser::parse(Rule::label_colon_whole, input)
+        .map_err(|e| explain(e, |_| check_entry(input, 0, &[':'], profile)))?;
     let first = pairs.next().unwrap();
     let label = match first.as_rule() {
-        Rule::label => match_label(first),
+        Rule::label_colon_spec => match_label_ref(first, profile)?,
         _ => unreachable!(),
     };
     match pairs.next().unwrap().as_rule() {
@@ -107,36 +552,486 @@ pub fn label_from_envstr(input: &str) -> Result<Label, Error> {
     }
 }
 
-pub fn labels_from_envstr(input: &str) -> Result<Vec<Label>, Error> {
-    let mut res = Vec::new();
-    for pair in LabelParser::parse(Rule::labels, input)? {
-        match pair.as_rule() {
-            Rule::label => res.push(match_label(pair)),
-            Rule::EOI => (),
-            _ => unreachable!(),
+pub fn labels_from_csvstr_wcolon(input: &str) -> Result<Vec<Label>, Error> {
+    labels_from_csvstr_wcolon_with_profile(input, Profile::default())
+}
+
+pub fn labels_from_csvstr_wcolon_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<Vec<Label>, Error> {
+    parse_labels(
+        Rule::labels_colon_csv,
+        Rule::label_colon_spec,
+        &[':'],
+        input,
+        profile,
+        |label| label.to_owned(),
+    )
+}
+
+pub fn labels_ref_from_csvstr_wcolon(input: &str) -> Result<Vec<LabelRef<'_>>, Error> {
+    labels_ref_from_csvstr_wcolon_with_profile(input, Profile::default())
+}
+
+pub fn labels_ref_from_csvstr_wcolon_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<Vec<LabelRef<'_>>, Error> {
+    parse_labels(
+        Rule::labels_colon_csv,
+        Rule::label_colon_spec,
+        &[':'],
+        input,
+        profile,
+        |label| label,
+    )
+}
+
+pub fn labels_from_wsvstr_wcolon(input: &str) -> Result<Vec<Label>, Error> {
+    labels_from_wsvstr_wcolon_with_profile(input, Profile::default())
+}
+
+pub fn labels_from_wsvstr_wcolon_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<Vec<Label>, Error> {
+    parse_labels(
+        Rule::labels_colon_wsv,
+        Rule::label_colon_spec,
+        &[':'],
+        input,
+        profile,
+        |label| label.to_owned(),
+    )
+}
+
+pub fn labels_ref_from_wsvstr_wcolon(input: &str) -> Result<Vec<LabelRef<'_>>, Error> {
+    labels_ref_from_wsvstr_wcolon_with_profile(input, Profile::default())
+}
+
+pub fn labels_ref_from_wsvstr_wcolon_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<Vec<LabelRef<'_>>, Error> {
+    parse_labels(
+        Rule::labels_colon_wsv,
+        Rule::label_colon_spec,
+        &[':'],
+        input,
+        profile,
+        |label| label,
+    )
+}
+
+pub fn labels_from_str_either(input: &str) -> Result<Vec<Label>, Error> {
+    labels_from_str_either_with_profile(input, Profile::default())
+}
+
+pub fn labels_from_str_either_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<Vec<Label>, Error> {
+    parse_labels(
+        Rule::labels_colon_either,
+        Rule::label_colon_spec,
+        &[':'],
+        input,
+        profile,
+        |label| label.to_owned(),
+    )
+}
+
+pub fn labels_ref_from_str_either(input: &str) -> Result<Vec<LabelRef<'_>>, Error> {
+    labels_ref_from_str_either_with_profile(input, Profile::default())
+}
+
+pub fn labels_ref_from_str_either_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<Vec<LabelRef<'_>>, Error> {
+    parse_labels(
+        Rule::labels_colon_either,
+        Rule::label_colon_spec,
+        &[':'],
+        input,
+        profile,
+        |label| label,
+    )
+}
+
+/// As `labels_from_envstr`, applying `policy` to keys given more than once
+///
+/// Along with the labels left, returns the duplicate keys resolved and the
+/// keys differing only in letter case, which are all kept unless `policy`
+/// is `ConflictPolicy::Error`.
+pub fn labels_from_envstr_with_policy(
+    input: &str,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Label>, Vec<Conflict>), Error> {
+    labels_from_envstr_with_profile_and_policy(input, Profile::default(), policy)
+}
+
+pub fn labels_from_envstr_with_profile_and_policy(
+    input: &str,
+    profile: Profile,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Label>, Vec<Conflict>), Error> {
+    let labels = parse_label_spans(Rule::labels, Rule::label, &['='], input, profile)?;
+    resolve_conflicts(labels, policy)
+}
+
+/// As `labels_from_csvstr_wcolon`, applying `policy` as
+/// `labels_from_envstr_with_policy` does
+pub fn labels_from_csvstr_wcolon_with_policy(
+    input: &str,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Label>, Vec<Conflict>), Error> {
+    labels_from_csvstr_wcolon_with_profile_and_policy(input, Profile::default(), policy)
+}
+
+pub fn labels_from_csvstr_wcolon_with_profile_and_policy(
+    input: &str,
+    profile: Profile,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Label>, Vec<Conflict>), Error> {
+    let labels = parse_label_spans(
+        Rule::labels_colon_csv,
+        Rule::label_colon_spec,
+        &[':'],
+        input,
+        profile,
+    )?;
+    resolve_conflicts(labels, policy)
+}
+
+/// As `labels_from_wsvstr_wcolon`, applying `policy` as
+/// `labels_from_envstr_with_policy` does
+pub fn labels_from_wsvstr_wcolon_with_policy(
+    input: &str,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Label>, Vec<Conflict>), Error> {
+    labels_from_wsvstr_wcolon_with_profile_and_policy(input, Profile::default(), policy)
+}
+
+pub fn labels_from_wsvstr_wcolon_with_profile_and_policy(
+    input: &str,
+    profile: Profile,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Label>, Vec<Conflict>), Error> {
+    let labels = parse_label_spans(
+        Rule::labels_colon_wsv,
+        Rule::label_colon_spec,
+        &[':'],
+        input,
+        profile,
+    )?;
+    resolve_conflicts(labels, policy)
+}
+
+/// As `labels_from_str_either`, applying `policy` as
+/// `labels_from_envstr_with_policy` does
+pub fn labels_from_str_either_with_policy(
+    input: &str,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Label>, Vec<Conflict>), Error> {
+    labels_from_str_either_with_profile_and_policy(input, Profile::default(), policy)
+}
+
+pub fn labels_from_str_either_with_profile_and_policy(
+    input: &str,
+    profile: Profile,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Label>, Vec<Conflict>), Error> {
+    let labels = parse_label_spans(
+        Rule::labels_colon_either,
+        Rule::label_colon_spec,
+        &[':'],
+        input,
+        profile,
+    )?;
+    resolve_conflicts(labels, policy)
+}
+
+/// Collect `labels` into a map, applying `policy` to keys given more than
+/// once, as the list parsers do
+///
+/// The spans of the conflicts returned are the positions of the labels in
+/// `labels`, e.g. `0..1` for the first one.
+#[cfg(feature = "std")]
+pub fn label_map_from_labels_with_policy<I: IntoIterator<Item = Label>>(
+    labels: I,
+    policy: ConflictPolicy,
+) -> Result<(LabelMap, Vec<Conflict>), Error> {
+    let labels = labels
+        .into_iter()
+        .enumerate()
+        .map(|(i, label)| (i..i + 1, label))
+        .collect();
+    let (labels, conflicts) = resolve_conflicts(labels, policy)?;
+    Ok((
+        labels.into_iter().map(Label::into_tuple).collect(),
+        conflicts,
+    ))
+}
+
+/// Whitespace as the grammar knows it
+fn is_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n'
+}
+
+/// The entries of a list and where each starts, empty ones included so they
+/// can be reported. With `merge_runs` set, runs of separators count as one.
+fn split_entries(input: &str, sep: fn(char) -> bool, merge_runs: bool) -> Vec<(usize, &str)> {
+    let mut entries = Vec::new();
+    let mut start = 0;
+    let ends = input
+        .char_indices()
+        .filter(|&(_, c)| sep(c))
+        .map(|(i, c)| (i, i + c.len_utf8()))
+        .chain(Some((input.len(), input.len())));
+    for (end, next) in ends {
+        let first_or_last = start == 0 || end == input.len();
+        if start < end || !merge_runs || first_or_last {
+            entries.push((start, &input[start..end]));
         }
+        start = next;
     }
-    Ok(res)
+    entries
 }
 
-pub fn label_from_str_wcolon(input: &str) -> Result<Label, Error> {
-    let mut pairs = LabelParser::parse(Rule::label_colon_whole, input)?;
+/// Parse every entry with `parse`, keeping the valid labels and the errors of
+/// the others
+fn collect_labels<'i>(
+    entries: Vec<(usize, &'i str)>,
+    parse: fn(&'i str, Profile) -> Result<LabelRef<'i>, Error>,
+    profile: Profile,
+) -> (Vec<Label>, Vec<EntryError>) {
+    let mut labels = Vec::new();
+    let mut errors = Vec::new();
+    for (index, (start, entry)) in entries.into_iter().enumerate() {
+        let res = if entry.is_empty() {
+            Err(Error::Syntax {
+                offset: 0,
+                message: "expected a label".to_string(),
+            })
+        } else {
+            parse(entry, profile)
+        };
+        match res {
+            Ok(label) => labels.push(label.to_owned()),
+            Err(error) => errors.push(EntryError {
+                index,
+                span: start..start + entry.len(),
+                error: error.shifted(start),
+            }),
+        }
+    }
+    (labels, errors)
+}
+
+/// As `labels_from_envstr`, but parsing every entry instead of stopping at
+/// the first invalid one
+pub fn labels_from_envstr_collect_errors(input: &str) -> (Vec<Label>, Vec<EntryError>) {
+    labels_from_envstr_collect_errors_with_profile(input, Profile::default())
+}
+
+pub fn labels_from_envstr_collect_errors_with_profile(
+    input: &str,
+    profile: Profile,
+) -> (Vec<Label>, Vec<EntryError>) {
+    // the list may be surrounded by whitespace
+    let trimmed = input.trim_start_matches(is_whitespace);
+    let start = input.len() - trimmed.len();
+    let trimmed = trimmed.trim_end_matches(is_whitespace);
+    let entries = split_entries(trimmed, is_whitespace, true)
+        .into_iter()
+        .map(|(i, entry)| (start + i, entry))
+        .collect();
+    collect_labels(entries, label_ref_from_envstr_with_profile, profile)
+}
+
+/// As `labels_from_csvstr_wcolon`, but parsing every entry instead of
+/// stopping at the first invalid one
+pub fn labels_from_csvstr_wcolon_collect_errors(input: &str) -> (Vec<Label>, Vec<EntryError>) {
+    labels_from_csvstr_wcolon_collect_errors_with_profile(input, Profile::default())
+}
+
+pub fn labels_from_csvstr_wcolon_collect_errors_with_profile(
+    input: &str,
+    profile: Profile,
+) -> (Vec<Label>, Vec<EntryError>) {
+    let entries = split_entries(input, |c| c == ',', false);
+    collect_labels(entries, label_ref_from_str_wcolon_with_profile, profile)
+}
+
+/// As `labels_from_wsvstr_wcolon`, but parsing every entry instead of
+/// stopping at the first invalid one
+pub fn labels_from_wsvstr_wcolon_collect_errors(input: &str) -> (Vec<Label>, Vec<EntryError>) {
+    labels_from_wsvstr_wcolon_collect_errors_with_profile(input, Profile::default())
+}
+
+pub fn labels_from_wsvstr_wcolon_collect_errors_with_profile(
+    input: &str,
+    profile: Profile,
+) -> (Vec<Label>, Vec<EntryError>) {
+    let entries = split_entries(input, is_whitespace, true);
+    collect_labels(entries, label_ref_from_str_wcolon_with_profile, profile)
+}
+
+/// As `labels_from_str_either`, but parsing every entry instead of stopping
+/// at the first invalid one. The first separator found decides whether the
+/// list is comma or whitespace separated.
+pub fn labels_from_str_either_collect_errors(input: &str) -> (Vec<Label>, Vec<EntryError>) {
+    labels_from_str_either_collect_errors_with_profile(input, Profile::default())
+}
+
+pub fn labels_from_str_either_collect_errors_with_profile(
+    input: &str,
+    profile: Profile,
+) -> (Vec<Label>, Vec<EntryError>) {
+    match input.find(|c| c == ',' || is_whitespace(c)) {
+        Some(i) if input[i..].starts_with(',') => {
+            labels_from_csvstr_wcolon_collect_errors_with_profile(input, profile)
+        }
+        _ => labels_from_wsvstr_wcolon_collect_errors_with_profile(input, profile),
+    }
+}
+
+/// Validate the keys and values of an unvalidated map, keeping the valid
+/// labels and returning the errors of the others along with their keys,
+/// ordered by key
+///
+/// The offsets of the errors point into the key or the value, as told by
+/// `Error::part`.
+#[cfg(feature = "std")]
+pub fn label_map_from_string_map(
+    map: &HashMap<String, String>,
+) -> (LabelMap, Vec<(String, Error)>) {
+    label_map_from_string_map_with_profile(map, Profile::default())
+}
+
+#[cfg(feature = "std")]
+pub fn label_map_from_string_map_with_profile(
+    map: &HashMap<String, String>,
+    profile: Profile,
+) -> (LabelMap, Vec<(String, Error)>) {
+    let mut labels = LabelMap::with_capacity(map.len());
+    let mut errors = Vec::new();
+    for (key, value) in map {
+        let label = label_key_ref_from_str_with_profile(key, profile).and_then(|key| {
+            let value = label_value_ref_from_str_with_profile(value, profile)?;
+            Ok((key.to_owned(), value.to_owned()))
+        });
+        match label {
+            Ok((key, value)) => {
+                labels.insert(key, value);
+            }
+            Err(err) => errors.push((key.clone(), err)),
+        }
+    }
+    errors.sort_by(|a, b| a.0.cmp(&b.0));
+    (labels, errors)
+}
+
+pub fn annotation_from_str(input: &str) -> Result<Annotation, Error> {
+    annotation_from_str_with_profile(input, Profile::default())
+}
+
+pub fn annotation_from_str_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<Annotation, Error> {
+    let mut pairs = LabelParser::parse(Rule::annotation_whole, input).map_err(|e| {
+        explain(e, |_| match input.find(&[':', '='][..]) {
+            Some(i) => check_key(&input[..i], 0, profile),
+            None => check_key(input, 0, profile),
+        })
+    })?;
     let first = pairs.next().unwrap();
-    let label = match first.as_rule() {
-        Rule::label_colon_spec => match_label(first),
+    let key = match first.as_rule() {
+        Rule::label_key => match_key(first, profile)?,
+        _ => unreachable!(),
+    };
+    let second = pairs.next().unwrap();
+    let value = match second.as_rule() {
+        Rule::annotation_value => second.as_str().to_string(),
+        Rule::EOI => {
+            return Ok(Annotation::new(key, "".to_string()));
+        }
         _ => unreachable!(),
     };
     match pairs.next().unwrap().as_rule() {
-        Rule::EOI => Ok(label),
+        Rule::EOI => Ok(Annotation::new(key, value)),
         _ => unreachable!(),
     }
 }
 
-pub fn labels_from_csvstr_wcolon(input: &str) -> Result<Vec<Label>, Error> {
+fn unescape_annotation_value(input: &str) -> String {
+    let mut res = String::with_capacity(input.len());
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => res.push('\n'),
+                escaped => res.extend(escaped),
+            },
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+fn match_annotation_list_entry(
+    part: pest::iterators::Pair<'_, Rule>,
+    profile: Profile,
+) -> Result<Annotation, Error> {
+    let mut i = part.into_inner();
+    let key = match_key(i.next().unwrap(), profile)?;
+    let value = match i.next() {
+        Some(value) => match value.as_rule() {
+            Rule::annotation_quoted_value => unescape_annotation_value(value.as_str()),
+            Rule::annotation_plain_value => value.as_str().to_string(),
+            _ => unreachable!(),
+        },
+        None => String::new(),
+    };
+    assert!(i.next().is_none());
+    Ok(Annotation::new(key, value))
+}
+
+fn is_annotation_separator(c: char) -> bool {
+    c == ',' || c == '\r' || c == '\n'
+}
+
+/// Parse a list of `key=value` annotations separated by commas or newlines
+///
+/// Values ending at the next separator are taken as is. Values holding
+/// separators must be double quoted, escaping `"` as `\"`, `\` as `\\` and
+/// newlines as `\n`. Empty entries, as in blank lines, are skipped.
+pub fn annotations_from_str(input: &str) -> Result<Annotations, Error> {
+    annotations_from_str_with_profile(input, Profile::default())
+}
+
+pub fn annotations_from_str_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<Annotations, Error> {
+    let pairs = LabelParser::parse(Rule::annotations_whole, input).map_err(|e| {
+        explain(e, |offset| {
+            match entry_at(input, offset, is_annotation_separator) {
+                Some((start, entry)) if !entry[..offset - start].contains('=') => {
+                    let key = entry.split('=').next().unwrap();
+                    check_key(key, start, profile)
+                }
+                _ => Ok(()),
+            }
+        })
+    })?;
     let mut res = Vec::new();
-    for pair in LabelParser::parse(Rule::labels_colon_csv, input)? {
+    for pair in pairs {
         match pair.as_rule() {
-            Rule::label_colon_spec => res.push(match_label(pair)),
+            Rule::annotation_list_entry => res.push(match_annotation_list_entry(pair, profile)?),
             Rule::EOI => (),
             _ => unreachable!(),
         }
@@ -144,23 +1039,127 @@ pub fn labels_from_csvstr_wcolon(input: &str) -> Result<Vec<Label>, Error> {
     Ok(res)
 }
 
-pub fn labels_from_wsvstr_wcolon(input: &str) -> Result<Vec<Label>, Error> {
-    let mut res = Vec::new();
-    for pair in LabelParser::parse(Rule::labels_colon_wsv, input)? {
-        match pair.as_rule() {
-            Rule::label_colon_spec => res.push(match_label(pair)),
-            Rule::EOI => (),
+/// Parsing of single lines of `.labels` files, which are read by `file`
+#[cfg(feature = "std")]
+pub(crate) mod labels_file {
+    use pest::Parser;
+
+    use super::*;
+
+    /// What a line of a `.labels` file holds
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub(crate) enum FileLine<'i> {
+        /// A blank line or a comment
+        Empty,
+        Label(Label),
+        /// An `@include` of `path` starting at `offset`
+        Include {
+            path: &'i str,
+            offset: usize,
+        },
+    }
+
+    fn is_line_space(c: char) -> bool {
+        c == ' ' || c == '\t'
+    }
+
+    /// Check the key and value of a label line, the value ending at whitespace
+    /// or a comment
+    fn check_file_line(line: &str, profile: Profile) -> Result<(), Error> {
+        let start = line.len() - line.trim_start_matches(is_line_space).len();
+        let entry = &line[start..];
+        let sep = match entry.find(&['=', ':'][..]) {
+            Some(sep) => sep,
+            None => return check_key(entry.trim_end_matches(is_line_space), start, profile),
+        };
+        check_key(&entry[..sep], start, profile)?;
+        let mut value_start = sep + 1;
+        if entry[sep..].starts_with(':') {
+            value_start =
+                entry.len() - entry[value_start..].trim_start_matches(is_line_space).len();
+        }
+        let value = &entry[value_start..];
+        let value_end = value
+            .find(|c| is_line_space(c) || c == '#')
+            .unwrap_or(value.len());
+        check_value(&value[..value_end], start + value_start, profile)
+    }
+
+    /// Parse a single line of a `.labels` file, without its line break
+    pub(crate) fn parse_line(line: &str, profile: Profile) -> Result<FileLine<'_>, Error> {
+        let mut pairs = LabelParser::parse(Rule::labels_file_line, line)
+            .map_err(|e| explain(e, |_| check_file_line(line, profile)))?;
+        let first = pairs.next().unwrap();
+        let res = match first.as_rule() {
+            Rule::labels_file_label => FileLine::Label(match_label_ref(first, profile)?.to_owned()),
+            Rule::labels_file_include => FileLine::Include {
+                offset: first.as_span().start(),
+                path: first.into_inner().next().unwrap().as_str(),
+            },
+            Rule::EOI => return Ok(FileLine::Empty),
+            _ => unreachable!(),
+        };
+        match pairs.next().unwrap().as_rule() {
+            Rule::EOI => Ok(res),
             _ => unreachable!(),
         }
     }
-    Ok(res)
 }
 
-pub fn labels_from_str_either(input: &str) -> Result<Vec<Label>, Error> {
-    let mut res = Vec::new();
-    for pair in LabelParser::parse(Rule::labels_colon_either, input)? {
+fn match_selector_requirement(
+    part: pest::iterators::Pair<'_, Rule>,
+    profile: Profile,
+) -> Result<Requirement, Error> {
+    let mut i = part.into_inner();
+    let first = i.next().unwrap();
+    let requirement = match first.as_rule() {
+        Rule::selector_not_exists => {
+            Requirement::does_not_exist(match_key(i.next().unwrap(), profile)?)
+        }
+        Rule::label_key => {
+            let key = match_key(first, profile)?;
+            let operator = match i.next() {
+                Some(op) => match op.as_str() {
+                    "=" => Operator::Equals,
+                    "==" => Operator::DoubleEquals,
+                    "!=" => Operator::NotEquals,
+                    "in" => Operator::In,
+                    "notin" => Operator::NotIn,
+                    _ => unreachable!(),
+                },
+                None => return Ok(Requirement::exists(key)),
+            };
+            let values = i
+                .by_ref()
+                .map(|v| match v.as_rule() {
+                    Rule::selector_value => match_value(v, profile),
+                    _ => unreachable!(),
+                })
+                .collect::<Result<_, _>>()?;
+            Requirement::new(key, operator, values)
+        }
+        _ => unreachable!(),
+    };
+    assert!(i.next().is_none());
+    Ok(requirement)
+}
+
+pub fn label_selector_from_str(input: &str) -> Result<LabelSelector, Error> {
+    label_selector_from_str_with_profile(input, Profile::default())
+}
+
+pub fn label_selector_from_str_with_profile(
+    input: &str,
+    profile: Profile,
+) -> Result<LabelSelector, Error> {
+    let mut res = LabelSelector::default();
+    for pair in LabelParser::parse(Rule::selector_whole, input).map_err(|e| {
+        explain(e, |offset| {
+            check_selector_requirement(input, offset, profile)
+        })
+    })? {
         match pair.as_rule() {
-            Rule::label_colon_spec => res.push(match_label(pair)),
+            Rule::selector_requirement => res.add(match_selector_requirement(pair, profile)?),
             Rule::EOI => (),
             _ => unreachable!(),
         }
@@ -168,23 +1167,67 @@ pub fn labels_from_str_either(input: &str) -> Result<Vec<Label>, Error> {
     Ok(res)
 }
 
-pub fn annotation_from_str(input: &str) -> Result<Annotation, Error> {
-    let mut pairs = LabelParser::parse(Rule::annotation_whole, input)?;
-    let first = pairs.next().unwrap();
-    let key = match first.as_rule() {
-        Rule::label_key => match_key(first),
+fn match_field_path(part: pest::iterators::Pair<'_, Rule>) -> FieldPath {
+    match part.as_rule() {
+        Rule::field_path => FieldPath(
+            part.into_inner()
+                .map(|segment| segment.as_str().to_string())
+                .collect(),
+        ),
+        _ => panic!("called with non-field-path rule"),
+    }
+}
+
+fn unescape_field_value(input: &str) -> String {
+    let mut res = String::with_capacity(input.len());
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => res.extend(chars.next()),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+fn match_field_requirement(part: pest::iterators::Pair<'_, Rule>) -> FieldRequirement {
+    let mut i = part.into_inner();
+    let path = match_field_path(i.next().unwrap());
+    let operator = match i.next().unwrap().as_str() {
+        "=" => FieldOperator::Equals,
+        "==" => FieldOperator::DoubleEquals,
+        "!=" => FieldOperator::NotEquals,
         _ => unreachable!(),
     };
-    let second = pairs.next().unwrap();
-    let value = match second.as_rule() {
-        Rule::annotation_value => second.as_str().to_string(),
-        Rule::EOI => {
-            return Ok(Annotation::new(key, "".to_string()));
-        }
+    let value = unescape_field_value(i.next().unwrap().as_str());
+    assert!(i.next().is_none());
+    FieldRequirement::new(path, operator, value)
+}
+
+/// Field paths and selectors hold no keys or label values, so they are the
+/// same in every `Profile` and have no `_with_profile` variants
+pub fn field_path_from_str(input: &str) -> Result<FieldPath, Error> {
+    let mut pairs = LabelParser::parse(Rule::field_path_whole, input)?;
+    let first = pairs.next().unwrap();
+    let path = match first.as_rule() {
+        Rule::field_path => match_field_path(first),
         _ => unreachable!(),
     };
     match pairs.next().unwrap().as_rule() {
-        Rule::EOI => Ok(Annotation::new(key, value)),
+        Rule::EOI => Ok(path),
         _ => unreachable!(),
     }
 }
+
+/// Parse a `--field-selector`, skipping empty terms as apimachinery does
+pub fn field_selector_from_str(input: &str) -> Result<FieldSelector, Error> {
+    let mut res = FieldSelector::default();
+    for pair in LabelParser::parse(Rule::field_selector_whole, input)? {
+        match pair.as_rule() {
+            Rule::field_requirement => res.add(match_field_requirement(pair)),
+            Rule::EOI => (),
+            _ => unreachable!(),
+        }
+    }
+    Ok(res)
+}
@@ -0,0 +1,300 @@
+//! Turn arbitrary strings into valid keys and values
+//!
+//! When characters are replaced or the result is truncated, a short hash of
+//! the original string is appended, so that such inputs keep mapping to
+//! distinct outputs. Trimming the ends and lowercasing append no hash, so
+//! inputs differing only in those, e.g. `-foo-` and `foo`, collide. The
+//! results are valid in every `Profile`.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::types::*;
+
+const MAX_NAME_LEN: usize = 63;
+const MAX_VALUE_LEN: usize = 63;
+const MAX_PREFIX_LEN: usize = 253;
+const MIN_DNS_LABEL_LEN: usize = 2;
+const MAX_DNS_LABEL_LEN: usize = 63;
+const HASH_LEN: usize = 8;
+
+fn is_alphanumeric(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+}
+
+fn is_name_char(c: char) -> bool {
+    is_alphanumeric(c) || c == '-' || c == '_' || c == '.'
+}
+
+fn is_dns_label_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'
+}
+
+/// What sanitizing did to one part of the input
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum Adjustment {
+    /// Uppercase letters were lowercased
+    Lowercased,
+    /// Runs of disallowed characters were replaced with `-`
+    Replaced,
+    /// Non alphanumeric characters were trimmed from the ends
+    Trimmed,
+    /// Empty DNS labels were removed
+    RemovedEmptyDnsLabels,
+    /// It was cut to fit its length limit
+    Truncated,
+    /// A hash of the original was appended, or used in place of nothing
+    Hashed,
+    /// Nothing of it was left, so it was dropped
+    Removed,
+}
+
+/// The adjustments made while sanitizing, in the order they were made
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct SanitizeReport {
+    adjustments: Vec<(Part, Adjustment)>,
+}
+
+impl SanitizeReport {
+    /// True if the input was valid and returned as is
+    pub fn is_unchanged(&self) -> bool {
+        self.adjustments.is_empty()
+    }
+    pub fn adjustments(&self) -> &[(Part, Adjustment)] {
+        &self.adjustments
+    }
+    pub fn contains(&self, part: Part, adjustment: Adjustment) -> bool {
+        self.adjustments.contains(&(part, adjustment))
+    }
+    fn note(&mut self, part: Part, adjustment: Adjustment) {
+        if !self.contains(part, adjustment) {
+            self.adjustments.push((part, adjustment));
+        }
+    }
+}
+
+/// 32 bit FNV-1a, stable across platforms and releases unlike `DefaultHasher`
+fn hash(input: &str) -> String {
+    let hash = input.bytes().fold(0x811c_9dc5u32, |hash, b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    });
+    format!("{:0width$x}", hash, width = HASH_LEN)
+}
+
+fn trim(input: &str) -> &str {
+    input.trim_matches(|c: char| !is_alphanumeric(c))
+}
+
+/// Cut `input`, made of ASCII characters only, to leave room for a hash and
+/// append the hash of `original`
+fn with_hash(input: &str, original: &str, max_len: usize) -> String {
+    let base = trim(&input[..input.len().min(max_len - HASH_LEN - 1)]);
+    if base.is_empty() {
+        hash(original)
+    } else {
+        format!("{}-{}", base, hash(original))
+    }
+}
+
+/// Sanitizes a single part, recording what was done in the report
+struct Sanitizer<'a> {
+    part: Part,
+    report: &'a mut SanitizeReport,
+}
+
+impl<'a> Sanitizer<'a> {
+    /// Replace every run of disallowed characters with a single `-`
+    fn replace_invalid(&mut self, input: &str, allowed: fn(char) -> bool) -> String {
+        let mut res = String::with_capacity(input.len());
+        let mut in_run = false;
+        for c in input.chars() {
+            if allowed(c) {
+                res.push(c);
+                in_run = false;
+            } else {
+                if !in_run {
+                    res.push('-');
+                }
+                self.report.note(self.part, Adjustment::Replaced);
+                in_run = true;
+            }
+        }
+        res
+    }
+
+    fn trim<'s>(&mut self, input: &'s str) -> &'s str {
+        let trimmed = trim(input);
+        if trimmed.len() != input.len() {
+            self.report.note(self.part, Adjustment::Trimmed);
+        }
+        trimmed
+    }
+
+    /// Trim and fit `input` into `max_len` characters, appending the hash of
+    /// `original` when characters were replaced in it or had to be cut
+    fn finish(&mut self, input: &str, original: &str, lossy: bool, max_len: usize) -> String {
+        let input = self.trim(input);
+        if !lossy && input.len() <= max_len {
+            return input.to_string();
+        }
+        if input.len() > max_len {
+            self.report.note(self.part, Adjustment::Truncated);
+        }
+        self.report.note(self.part, Adjustment::Hashed);
+        with_hash(input, original, max_len)
+    }
+
+    /// Sanitize a key name or a value, possibly leaving it empty
+    fn name_or_value(&mut self, input: &str, max_len: usize) -> String {
+        let replaced = self.replace_invalid(input, is_name_char);
+        let lossy = replaced != input;
+        self.finish(&replaced, input, lossy, max_len)
+    }
+
+    /// Sanitize a key prefix, or `None` if nothing of it is left
+    fn prefix(&mut self, input: &str) -> Option<String> {
+        let lowercase = input.to_ascii_lowercase();
+        if lowercase != input {
+            self.report.note(self.part, Adjustment::Lowercased);
+        }
+        let mut labels = Vec::new();
+        for label in lowercase.split('.') {
+            let replaced = self.replace_invalid(label, is_dns_label_char);
+            let lossy = replaced != label;
+            let label = self.finish(&replaced, label, lossy, MAX_DNS_LABEL_LEN);
+            if label.is_empty() {
+                self.report
+                    .note(self.part, Adjustment::RemovedEmptyDnsLabels);
+            } else if label.len() < MIN_DNS_LABEL_LEN {
+                // single character labels are only valid in `Profile::Strict`
+                self.report.note(self.part, Adjustment::Hashed);
+                labels.push(format!("{}-{}", label, hash(&label)));
+            } else {
+                labels.push(label);
+            }
+        }
+        if labels.is_empty() {
+            return None;
+        }
+        let prefix = labels.join(".");
+        if prefix.len() <= MAX_PREFIX_LEN {
+            return Some(prefix);
+        }
+        self.report.note(self.part, Adjustment::Truncated);
+        self.report.note(self.part, Adjustment::Hashed);
+        let mut base = trim(&prefix[..MAX_PREFIX_LEN - HASH_LEN - 1]);
+        // the hash goes into the last label, which must stay within its limit
+        let last = base.rfind('.').map_or(0, |i| i + 1);
+        if base.len() - last > MAX_DNS_LABEL_LEN - HASH_LEN - 1 {
+            base = trim(&base[..last + MAX_DNS_LABEL_LEN - HASH_LEN - 1]);
+        }
+        Some(format!("{}-{}", base, hash(input)))
+    }
+}
+
+fn name(input: &str, report: &mut SanitizeReport) -> KeyName {
+    let mut sanitizer = Sanitizer {
+        part: Part::Name,
+        report,
+    };
+    let name = sanitizer.name_or_value(input, MAX_NAME_LEN);
+    if name.is_empty() {
+        sanitizer.report.note(Part::Name, Adjustment::Hashed);
+        KeyName(hash(input))
+    } else {
+        KeyName(name)
+    }
+}
+
+impl LabelValue {
+    /// Turn any string into a valid label value
+    ///
+    /// Runs of disallowed characters are replaced with `-`, non alphanumeric
+    /// characters are trimmed from both ends and the result is cut to 63
+    /// characters. When characters had to be replaced or cut, a hash of
+    /// `input` is appended, e.g. `feature/login` becomes
+    /// `feature-login-28ac1333`. Values valid in every `Profile` are returned
+    /// unchanged.
+    pub fn sanitize(input: &str) -> LabelValue {
+        LabelValue::sanitize_with_report(input).0
+    }
+    pub fn sanitize_with_report(input: &str) -> (LabelValue, SanitizeReport) {
+        let mut report = SanitizeReport::default();
+        let value = Sanitizer {
+            part: Part::Value,
+            report: &mut report,
+        }
+        .name_or_value(input, MAX_VALUE_LEN);
+        (LabelValue(value), report)
+    }
+}
+
+impl KeyName {
+    /// Turn any string into a valid key name
+    ///
+    /// The same rules as for `LabelValue::sanitize` apply, except that a name
+    /// with nothing left is replaced by the hash of `input`.
+    pub fn sanitize(input: &str) -> KeyName {
+        KeyName::sanitize_with_report(input).0
+    }
+    pub fn sanitize_with_report(input: &str) -> (KeyName, SanitizeReport) {
+        let mut report = SanitizeReport::default();
+        (name(input, &mut report), report)
+    }
+}
+
+impl KeyPrefix {
+    /// Turn any string into a valid key prefix
+    ///
+    /// The prefix is lowercased, runs of characters other than `[a-z0-9-]`
+    /// are replaced with `-` in every DNS label and empty labels are removed.
+    /// Labels are kept within 2 to 63 characters and the prefix within 253,
+    /// appending hashes as for `LabelValue::sanitize`. A prefix with nothing
+    /// left is replaced by the hash of `input`.
+    pub fn sanitize(input: &str) -> KeyPrefix {
+        KeyPrefix::sanitize_with_report(input).0
+    }
+    pub fn sanitize_with_report(input: &str) -> (KeyPrefix, SanitizeReport) {
+        let mut report = SanitizeReport::default();
+        let mut sanitizer = Sanitizer {
+            part: Part::Prefix,
+            report: &mut report,
+        };
+        let prefix = sanitizer.prefix(input).unwrap_or_else(|| {
+            sanitizer.report.note(Part::Prefix, Adjustment::Hashed);
+            hash(input)
+        });
+        (KeyPrefix(prefix), report)
+    }
+}
+
+impl Key {
+    /// Turn any string into a valid key
+    ///
+    /// Anything before the first `/` is sanitized as a `KeyPrefix` and the
+    /// rest as a `KeyName`, but unlike `KeyPrefix::sanitize` a prefix with
+    /// nothing left is dropped.
+    pub fn sanitize(input: &str) -> Key {
+        Key::sanitize_with_report(input).0
+    }
+    pub fn sanitize_with_report(input: &str) -> (Key, SanitizeReport) {
+        let mut report = SanitizeReport::default();
+        let (prefix, name_input) = match input.find('/') {
+            Some(i) => {
+                let prefix = Sanitizer {
+                    part: Part::Prefix,
+                    report: &mut report,
+                }
+                .prefix(&input[..i]);
+                if prefix.is_none() {
+                    report.note(Part::Prefix, Adjustment::Removed);
+                }
+                (prefix.map(KeyPrefix), &input[i + 1..])
+            }
+            None => (None, input),
+        };
+        let name = name(name_input, &mut report);
+        (Key::new(prefix, name), report)
+    }
+}
@@ -0,0 +1,247 @@
+//! A hand-written validator for keys and values
+//!
+//! Accepts exactly what `labels.pest` and the checks in `validate` accept and
+//! reports the same errors, but in a single scan of the input without the
+//! backtracking of the grammar's lookaheads.
+
+use crate::borrowed::*;
+use crate::types::{Error, Part, Profile};
+use crate::validate::{
+    MAX_DNS_LABEL_LEN, MAX_NAME_LEN, MAX_PREFIX_LEN, MAX_STRICT_PREFIX_LEN, MAX_VALUE_LEN,
+    MIN_DNS_LABEL_LEN,
+};
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.'
+}
+
+fn is_dns_label_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-'
+}
+
+fn is_strict_dns_label_byte(b: u8) -> bool {
+    b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-'
+}
+
+fn char_at(input: &str, i: usize) -> char {
+    input[i..].chars().next().unwrap()
+}
+
+/// The error for the non empty part `input[start..end]`, given its first
+/// invalid byte, in the order `validate::check_part` reports them
+fn check_part(
+    input: &str,
+    start: usize,
+    end: usize,
+    invalid: Option<usize>,
+    part: Part,
+) -> Result<(), Error> {
+    let bytes = input.as_bytes();
+    if let Some(i) = invalid {
+        return Err(Error::InvalidCharacter {
+            part,
+            ch: char_at(input, i),
+            offset: i,
+        });
+    }
+    if !bytes[start].is_ascii_alphanumeric() {
+        return Err(Error::MustStartWithAlphanumeric {
+            part,
+            offset: start,
+        });
+    }
+    if !bytes[end - 1].is_ascii_alphanumeric() {
+        return Err(Error::MustEndWithAlphanumeric {
+            part,
+            offset: end - 1,
+        });
+    }
+    Ok(())
+}
+
+/// Scans a key prefix one byte at a time, keeping the first error
+struct PrefixScan {
+    profile: Profile,
+    allowed: fn(u8) -> bool,
+    start: usize,
+    label_start: usize,
+    label_invalid: Option<usize>,
+    error: Option<Error>,
+}
+
+impl PrefixScan {
+    fn new(start: usize, profile: Profile) -> Self {
+        PrefixScan {
+            profile,
+            allowed: match profile {
+                Profile::Strict => is_strict_dns_label_byte,
+                Profile::Lenient => is_dns_label_byte,
+            },
+            start,
+            label_start: start,
+            label_invalid: None,
+            error: None,
+        }
+    }
+
+    fn push(&mut self, input: &str, i: usize, b: u8) {
+        if self.error.is_some() {
+            return;
+        }
+        if b == b'.' {
+            self.end_label(input, i);
+            self.label_start = i + 1;
+            self.label_invalid = None;
+        } else if self.label_invalid.is_none() && !(self.allowed)(b) {
+            self.label_invalid = Some(i);
+        }
+    }
+
+    fn end_label(&mut self, input: &str, end: usize) {
+        let (start, len) = (self.label_start, end - self.label_start);
+        let res = if len == 0 {
+            Err(Error::EmptyDnsLabel { offset: start })
+        } else {
+            check_part(input, start, end, self.label_invalid, Part::Prefix)
+        };
+        self.error = match res {
+            Err(err) => Some(err),
+            // kubernetes itself only limits the length of the whole subdomain
+            Ok(()) if self.profile == Profile::Lenient && len > MAX_DNS_LABEL_LEN => {
+                Some(Error::DnsLabelTooLong { len, offset: start })
+            }
+            Ok(()) if self.profile == Profile::Lenient && len < MIN_DNS_LABEL_LEN => {
+                Some(Error::DnsLabelTooShort { len, offset: start })
+            }
+            Ok(()) => None,
+        };
+    }
+
+    fn finish(mut self, input: &str, end: usize) -> Result<(), Error> {
+        if self.error.is_none() {
+            self.end_label(input, end);
+        }
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let max = match self.profile {
+            Profile::Strict => MAX_STRICT_PREFIX_LEN,
+            Profile::Lenient => MAX_PREFIX_LEN,
+        };
+        let len = end - self.start;
+        if len > max {
+            return Err(Error::PrefixTooLong {
+                len,
+                max,
+                offset: self.start,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Check the key name or value `input[start..]`, whose first byte not allowed
+/// in a name, if any, is `invalid`
+fn check_name_or_value(
+    input: &str,
+    start: usize,
+    invalid: Option<usize>,
+    part: Part,
+    max_len: Option<usize>,
+) -> Result<(), Error> {
+    let len = input.len() - start;
+    check_part(input, start, input.len(), invalid, part)?;
+    match max_len {
+        Some(max_len) if len > max_len => Err(match part {
+            Part::Value => Error::ValueTooLong { len, offset: start },
+            _ => Error::KeyNameTooLong { len, offset: start },
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn first_invalid_name_byte(input: &str, start: usize) -> Option<usize> {
+    input.as_bytes()[start..]
+        .iter()
+        .position(|&b| !is_name_byte(b))
+        .map(|i| start + i)
+}
+
+pub(crate) fn prefix(input: &str, profile: Profile) -> Result<&str, Error> {
+    let mut scan = PrefixScan::new(0, profile);
+    for (i, &b) in input.as_bytes().iter().enumerate() {
+        scan.push(input, i, b);
+    }
+    scan.finish(input, input.len())?;
+    Ok(input)
+}
+
+/// As in the grammar, names on their own are only limited in length by
+/// `Profile::Strict`
+pub(crate) fn name(input: &str, profile: Profile) -> Result<&str, Error> {
+    if input.is_empty() {
+        return Err(Error::EmptyName { offset: 0 });
+    }
+    let max_len = match profile {
+        Profile::Strict => Some(MAX_NAME_LEN),
+        Profile::Lenient => None,
+    };
+    check_name_or_value(
+        input,
+        0,
+        first_invalid_name_byte(input, 0),
+        Part::Name,
+        max_len,
+    )?;
+    Ok(input)
+}
+
+pub(crate) fn key(input: &str, profile: Profile) -> Result<KeyRef<'_>, Error> {
+    let bytes = input.as_bytes();
+    // until a `/` is found, the input is read both as a prefix and as a name
+    let mut scan = PrefixScan::new(0, profile);
+    let mut name_invalid = None;
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'/' {
+            scan.finish(input, i)?;
+            let start = i + 1;
+            if start == input.len() {
+                return Err(Error::EmptyName { offset: start });
+            }
+            check_name_or_value(
+                input,
+                start,
+                first_invalid_name_byte(input, start),
+                Part::Name,
+                Some(MAX_NAME_LEN),
+            )?;
+            return Ok(KeyRef::new(Some(&input[..i]), &input[start..]));
+        }
+        scan.push(input, i, b);
+        if name_invalid.is_none() && !is_name_byte(b) {
+            name_invalid = Some(i);
+        }
+    }
+    if input.is_empty() {
+        return Err(Error::EmptyName { offset: 0 });
+    }
+    check_name_or_value(input, 0, name_invalid, Part::Name, Some(MAX_NAME_LEN))?;
+    Ok(KeyRef::new(None, input))
+}
+
+pub(crate) fn value(input: &str, profile: Profile) -> Result<LabelValueRef<'_>, Error> {
+    if !input.is_empty() {
+        let max_len = match profile {
+            Profile::Strict => Some(MAX_VALUE_LEN),
+            Profile::Lenient => None,
+        };
+        check_name_or_value(
+            input,
+            0,
+            first_invalid_name_byte(input, 0),
+            Part::Value,
+            max_len,
+        )?;
+    }
+    Ok(LabelValueRef(input))
+}
@@ -0,0 +1,379 @@
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+
+use crate::parser;
+use crate::types::*;
+
+/// The operator of a single selector requirement
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord)]
+pub enum Operator {
+    /// `key=value`
+    Equals,
+    /// `key==value`
+    DoubleEquals,
+    /// `key!=value`
+    NotEquals,
+    /// `key in (value1,value2)`
+    In,
+    /// `key notin (value1,value2)`
+    NotIn,
+    /// `key`
+    Exists,
+    /// `!key`
+    DoesNotExist,
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Equals => "=",
+            Operator::DoubleEquals => "==",
+            Operator::NotEquals => "!=",
+            Operator::In => "in",
+            Operator::NotIn => "notin",
+            Operator::Exists => "exists",
+            Operator::DoesNotExist => "!",
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A single requirement of a label selector, e.g. `tier!=frontend` or `env in (prod,staging)`
+///
+/// `values` holds one value for the equality operators, any number for
+/// `In`/`NotIn` and none for `Exists`/`DoesNotExist`.
+#[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord)]
+pub struct Requirement {
+    pub key: Key,
+    pub operator: Operator,
+    pub values: BTreeSet<LabelValue>,
+}
+
+impl Requirement {
+    pub fn new(key: Key, operator: Operator, values: BTreeSet<LabelValue>) -> Self {
+        Requirement {
+            key,
+            operator,
+            values,
+        }
+    }
+    pub fn equals(key: Key, value: LabelValue) -> Self {
+        Requirement::new(key, Operator::Equals, Some(value).into_iter().collect())
+    }
+    pub fn not_equals(key: Key, value: LabelValue) -> Self {
+        Requirement::new(key, Operator::NotEquals, Some(value).into_iter().collect())
+    }
+    pub fn in_set(key: Key, values: BTreeSet<LabelValue>) -> Self {
+        Requirement::new(key, Operator::In, values)
+    }
+    pub fn not_in_set(key: Key, values: BTreeSet<LabelValue>) -> Self {
+        Requirement::new(key, Operator::NotIn, values)
+    }
+    pub fn exists(key: Key) -> Self {
+        Requirement::new(key, Operator::Exists, BTreeSet::new())
+    }
+    pub fn does_not_exist(key: Key) -> Self {
+        Requirement::new(key, Operator::DoesNotExist, BTreeSet::new())
+    }
+
+    /// Fold the equality operators into their single valued set equivalents
+    pub fn normalize(self) -> Self {
+        let operator = match self.operator {
+            Operator::Equals | Operator::DoubleEquals => Operator::In,
+            Operator::NotEquals => Operator::NotIn,
+            operator => operator,
+        };
+        Requirement { operator, ..self }
+    }
+
+    /// Check whether the requirement holds for the given labels
+    #[cfg(feature = "std")]
+    pub fn matches(&self, labels: &LabelMap) -> bool {
+        match self.operator {
+            Operator::Equals | Operator::DoubleEquals | Operator::In => {
+                match labels.get(&self.key) {
+                    Some(value) => self.values.contains(value),
+                    None => false,
+                }
+            }
+            Operator::NotEquals | Operator::NotIn => match labels.get(&self.key) {
+                Some(value) => !self.values.contains(value),
+                None => true,
+            },
+            Operator::Exists => labels.contains_key(&self.key),
+            Operator::DoesNotExist => !labels.contains_key(&self.key),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        match self.operator {
+            Operator::Exists => write!(f, "{}", self.key),
+            Operator::DoesNotExist => write!(f, "!{}", self.key),
+            Operator::In | Operator::NotIn => {
+                write!(f, "{} {} (", self.key, self.operator)?;
+                for (i, value) in self.values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, ")")
+            }
+            _ => {
+                write!(f, "{}{}", self.key, self.operator)?;
+                if let Some(value) = self.values.iter().next() {
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A kubernetes label selector, the AND of all of its requirements
+///
+/// An empty selector matches every set of labels.
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Default)]
+pub struct LabelSelector {
+    requirements: Vec<Requirement>,
+}
+
+parse_from!(
+    LabelSelector,
+    parser::label_selector_from_str,
+    parser::label_selector_from_str_with_profile
+);
+
+impl LabelSelector {
+    pub fn new(requirements: Vec<Requirement>) -> Self {
+        LabelSelector { requirements }
+    }
+    pub fn requirements(&self) -> &[Requirement] {
+        &self.requirements
+    }
+    pub fn into_requirements(self) -> Vec<Requirement> {
+        self.requirements
+    }
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+    pub fn add(&mut self, requirement: Requirement) {
+        self.requirements.push(requirement);
+    }
+    /// Check whether every requirement holds for the given labels
+    #[cfg(feature = "std")]
+    pub fn matches(&self, labels: &LabelMap) -> bool {
+        self.requirements.iter().all(|req| req.matches(labels))
+    }
+    /// Bring the selector into its canonical form
+    ///
+    /// Equality requirements are folded into single valued `in`/`notin`
+    /// requirements, requirements are sorted by the string form of their key
+    /// and duplicates are dropped, so two selectors matching by the same
+    /// rules compare equal and display identically.
+    pub fn normalize(self) -> Self {
+        let mut requirements: Vec<Requirement> = self
+            .requirements
+            .into_iter()
+            .map(Requirement::normalize)
+            .collect();
+        requirements.sort_by(canonical_order);
+        requirements.dedup();
+        LabelSelector { requirements }
+    }
+    pub fn is_normalized(&self) -> bool {
+        self.requirements
+            .windows(2)
+            .all(|pair| canonical_order(&pair[0], &pair[1]) == Ordering::Less)
+            && self.requirements.iter().all(|req| {
+                !matches!(
+                    req.operator,
+                    Operator::Equals | Operator::DoubleEquals | Operator::NotEquals
+                )
+            })
+    }
+}
+
+/// Requirements sorted by the string form of their keys, as apimachinery
+/// sorts them, then by operator and values
+fn canonical_order(a: &Requirement, b: &Requirement) -> Ordering {
+    a.key
+        .bytes()
+        .cmp(b.key.bytes())
+        .then_with(|| (a.operator, &a.values).cmp(&(b.operator, &b.values)))
+}
+
+impl fmt::Display for LabelSelector {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        for (i, req) in self.requirements.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", req)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::iter::FromIterator<Requirement> for LabelSelector {
+    fn from_iter<I: IntoIterator<Item = Requirement>>(iter: I) -> Self {
+        LabelSelector::new(iter.into_iter().collect())
+    }
+}
+
+#[cfg(feature = "serde_support")]
+pub use self::serde_extras::*;
+
+/// The structured `metav1.LabelSelector` form used in kubernetes manifests
+#[cfg(feature = "serde_support")]
+mod serde_extras {
+    use alloc::collections::BTreeMap;
+    use alloc::vec::Vec;
+    use core::convert::TryFrom;
+    use serde::{Deserialize, Serialize};
+
+    use super::{LabelSelector, Operator, Requirement};
+    use crate::types::{Error, Key, LabelValue};
+
+    /// The operator of a `matchExpressions` entry
+    #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+    pub enum ExpressionOperator {
+        In,
+        NotIn,
+        Exists,
+        DoesNotExist,
+    }
+
+    #[derive(Deserialize)]
+    struct RawLabelSelectorRequirement {
+        key: Key,
+        operator: ExpressionOperator,
+        #[serde(default)]
+        values: Vec<LabelValue>,
+    }
+
+    /// A single `matchExpressions` entry
+    #[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+    #[serde(try_from = "RawLabelSelectorRequirement")]
+    pub struct LabelSelectorRequirement {
+        pub key: Key,
+        pub operator: ExpressionOperator,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub values: Vec<LabelValue>,
+    }
+
+    impl LabelSelectorRequirement {
+        pub fn new(
+            key: Key,
+            operator: ExpressionOperator,
+            values: Vec<LabelValue>,
+        ) -> Result<Self, Error> {
+            match operator {
+                ExpressionOperator::In | ExpressionOperator::NotIn if values.is_empty() => Err(
+                    Error::InvalidRequirement("values must be non-empty for In and NotIn"),
+                ),
+                ExpressionOperator::Exists | ExpressionOperator::DoesNotExist
+                    if !values.is_empty() =>
+                {
+                    Err(Error::InvalidRequirement(
+                        "values must be empty for Exists and DoesNotExist",
+                    ))
+                }
+                _ => Ok(LabelSelectorRequirement {
+                    key,
+                    operator,
+                    values,
+                }),
+            }
+        }
+    }
+
+    impl TryFrom<RawLabelSelectorRequirement> for LabelSelectorRequirement {
+        type Error = Error;
+        fn try_from(raw: RawLabelSelectorRequirement) -> Result<Self, Self::Error> {
+            LabelSelectorRequirement::new(raw.key, raw.operator, raw.values)
+        }
+    }
+
+    /// A kubernetes `LabelSelector` object with `matchLabels` and `matchExpressions`
+    #[derive(PartialEq, Eq, Debug, Clone, Hash, Default, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct LabelSelectorSpec {
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub match_labels: BTreeMap<Key, LabelValue>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub match_expressions: Vec<LabelSelectorRequirement>,
+    }
+
+    impl LabelSelectorSpec {
+        /// Bring the spec into its canonical form, see `LabelSelector::normalize`
+        ///
+        /// `matchLabels` are folded into single valued `In` expressions.
+        pub fn normalize(self) -> Self {
+            LabelSelectorSpec::from(LabelSelector::from(self).normalize())
+        }
+    }
+
+    impl From<LabelSelectorRequirement> for Requirement {
+        fn from(req: LabelSelectorRequirement) -> Self {
+            let operator = match req.operator {
+                ExpressionOperator::In => Operator::In,
+                ExpressionOperator::NotIn => Operator::NotIn,
+                ExpressionOperator::Exists => Operator::Exists,
+                ExpressionOperator::DoesNotExist => Operator::DoesNotExist,
+            };
+            Requirement::new(req.key, operator, req.values.into_iter().collect())
+        }
+    }
+
+    impl From<LabelSelectorSpec> for LabelSelector {
+        fn from(spec: LabelSelectorSpec) -> Self {
+            spec.match_labels
+                .into_iter()
+                .map(|(key, value)| Requirement::equals(key, value))
+                .chain(spec.match_expressions.into_iter().map(Requirement::from))
+                .collect()
+        }
+    }
+
+    /// Equality requirements become `matchLabels` entries unless their key is
+    /// already taken, in which case they become single valued `In` expressions.
+    impl From<LabelSelector> for LabelSelectorSpec {
+        fn from(selector: LabelSelector) -> Self {
+            let mut spec = LabelSelectorSpec::default();
+            for req in selector.into_requirements() {
+                let operator = match req.operator {
+                    Operator::Equals | Operator::DoubleEquals => {
+                        if let (false, Some(value)) = (
+                            spec.match_labels.contains_key(&req.key),
+                            req.values.iter().next(),
+                        ) {
+                            spec.match_labels.insert(req.key, value.clone());
+                            continue;
+                        }
+                        ExpressionOperator::In
+                    }
+                    Operator::In => ExpressionOperator::In,
+                    Operator::NotEquals | Operator::NotIn => ExpressionOperator::NotIn,
+                    Operator::Exists => ExpressionOperator::Exists,
+                    Operator::DoesNotExist => ExpressionOperator::DoesNotExist,
+                };
+                spec.match_expressions.push(LabelSelectorRequirement {
+                    key: req.key,
+                    operator,
+                    values: req.values.into_iter().collect(),
+                });
+            }
+            spec
+        }
+    }
+}
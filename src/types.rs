@@ -1,8 +1,11 @@
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
 #[cfg(feature = "serde_support")]
 use serde::Serialize;
+#[cfg(feature = "std")]
 use std::collections::HashMap;
-use std::fmt;
-use std::hash::Hash;
 
 use crate::parser;
 
@@ -20,26 +23,26 @@ macro_rules! string_newtype {
             }
         }
 
-        impl std::ops::Deref for $ty {
+        impl core::ops::Deref for $ty {
             type Target = str;
             fn deref(&self) -> &Self::Target {
                 self.as_str()
             }
         }
 
-        impl std::convert::From<$ty> for String {
+        impl core::convert::From<$ty> for String {
             fn from(val: $ty) -> Self {
                 val.to_string()
             }
         }
 
-        impl std::borrow::Borrow<str> for $ty {
+        impl core::borrow::Borrow<str> for $ty {
             fn borrow(&self) -> &str {
                 self.as_str()
             }
         }
 
-        impl std::convert::AsRef<str> for $ty {
+        impl core::convert::AsRef<str> for $ty {
             fn as_ref(&self) -> &str {
                 self.as_str()
             }
@@ -48,6 +51,15 @@ macro_rules! string_newtype {
 }
 
 macro_rules! parse_from {
+    ($ty:ident, $parse_func:path, $parse_with_profile_func:path) => {
+        impl $ty {
+            pub fn parse_str_with_profile(input: &str, profile: Profile) -> Result<$ty, Error> {
+                $parse_with_profile_func(input, profile)
+            }
+        }
+
+        parse_from!($ty, $parse_func);
+    };
     ($ty:ident, $parse_func:path) => {
         impl $ty {
             pub fn parse_str(input: &str) -> Result<$ty, Error> {
@@ -55,14 +67,14 @@ macro_rules! parse_from {
             }
         }
 
-        impl std::convert::TryFrom<&str> for $ty {
+        impl core::convert::TryFrom<&str> for $ty {
             type Error = Error;
             fn try_from(value: &str) -> Result<Self, Self::Error> {
                 $ty::parse_str(value)
             }
         }
 
-        impl std::str::FromStr for $ty {
+        impl core::str::FromStr for $ty {
             type Err = Error;
             fn from_str(s: &str) -> Result<Self, Self::Err> {
                 $ty::parse_str(s)
@@ -71,20 +83,373 @@ macro_rules! parse_from {
     };
 }
 
-#[derive(Debug, thiserror::Error)]
+/// The validation rules keys and values are held to
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, Default)]
+pub enum Profile {
+    /// Exactly apimachinery's `IsQualifiedName` and `IsValidLabelValue`:
+    /// lowercase prefixes of at most 253 characters and values of at most 63
+    Strict,
+    /// The rules klap has always applied: mixed case prefixes of at most 255
+    /// characters made of 2 to 63 character DNS labels, and values of any
+    /// length
+    #[default]
+    Lenient,
+}
+
+/// The part of a key or label a validation error refers to
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum Part {
+    Prefix,
+    Name,
+    Value,
+}
+
+impl fmt::Display for Part {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        match self {
+            Part::Prefix => write!(f, "key prefix"),
+            Part::Name => write!(f, "key name"),
+            Part::Value => write!(f, "value"),
+        }
+    }
+}
+
+/// All offsets are byte offsets into the string handed to the parser
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Error {
-    #[error("{0}")]
-    ParserError(#[from] pest::error::Error<parser::Rule>),
-    //#[error("{0}")]
-    //CustomError(String),
+    Syntax {
+        offset: usize,
+        message: String,
+    },
+    EmptyName {
+        offset: usize,
+    },
+    EmptyDnsLabel {
+        offset: usize,
+    },
+    KeyNameTooLong {
+        len: usize,
+        offset: usize,
+    },
+    PrefixTooLong {
+        len: usize,
+        max: usize,
+        offset: usize,
+    },
+    DnsLabelTooLong {
+        len: usize,
+        offset: usize,
+    },
+    DnsLabelTooShort {
+        len: usize,
+        offset: usize,
+    },
+    ValueTooLong {
+        len: usize,
+        offset: usize,
+    },
+    InvalidCharacter {
+        part: Part,
+        ch: char,
+        offset: usize,
+    },
+    MustStartWithAlphanumeric {
+        part: Part,
+        offset: usize,
+    },
+    MustEndWithAlphanumeric {
+        part: Part,
+        offset: usize,
+    },
+    InvalidEscape {
+        offset: usize,
+    },
+    InvalidEncoding(&'static str),
+    InvalidRequirement(&'static str),
+    DuplicateKey {
+        first: usize,
+        offset: usize,
+    },
+    KeyCaseCollision {
+        first: usize,
+        offset: usize,
+    },
+    /// Arguments of `kubectl label` or `kubectl annotate` kubectl rejects,
+    /// with kubectl's message
+    InvalidCommand(String),
+    /// An invalid key or value in the argument at `index` of a command line,
+    /// counting from 0, the offset of `error` being into that argument
+    InvalidArgument {
+        index: usize,
+        error: Box<Error>,
+    },
+    /// Labels `kubectl label` would set without `--overwrite` that already
+    /// have other values, along with those values
+    WouldOverwriteLabels(Vec<(Key, String)>),
+    /// As `WouldOverwriteLabels`, for `kubectl annotate`
+    WouldOverwriteAnnotations(Vec<(Key, String)>),
 }
 
-//#[cfg(feature="serde_support")]
-//impl serde::de::Error for Error {
-//    fn custom<T>(msg: T) -> Self where T: fmt::Display {
-//        Error::CustomError(format!("{}", msg))
-//    }
-//}
+impl fmt::Display for Error {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        match self {
+            Error::Syntax { offset, message } => {
+                write!(f, "syntax error at offset {}: {}", offset, message)
+            }
+            Error::EmptyName { offset } => {
+                write!(f, "key name must not be empty (at offset {})", offset)
+            }
+            Error::EmptyDnsLabel { offset } => write!(
+                f,
+                "key prefix must not contain an empty DNS label (at offset {})",
+                offset
+            ),
+            Error::KeyNameTooLong { len, offset } => write!(
+                f,
+                "key name is too long: {} characters, at most 63 allowed (at offset {})",
+                len, offset
+            ),
+            Error::PrefixTooLong { len, max, offset } => write!(
+                f,
+                "key prefix is too long: {} characters, at most {} allowed (at offset {})",
+                len, max, offset
+            ),
+            Error::DnsLabelTooLong { len, offset } => write!(
+                f,
+                "DNS label in key prefix is too long: {} characters, at most 63 allowed (at offset {})",
+                len, offset
+            ),
+            Error::DnsLabelTooShort { len, offset } => write!(
+                f,
+                "DNS label in key prefix is too short: {} characters, at least 2 required (at offset {})",
+                len, offset
+            ),
+            Error::ValueTooLong { len, offset } => write!(
+                f,
+                "value is too long: {} characters, at most 63 allowed (at offset {})",
+                len, offset
+            ),
+            Error::InvalidCharacter { part, ch, offset } => write!(
+                f,
+                "invalid character {:?} in {} (at offset {})",
+                ch, part, offset
+            ),
+            Error::MustStartWithAlphanumeric { part, offset } => write!(
+                f,
+                "{} must start with an alphanumeric character (at offset {})",
+                part, offset
+            ),
+            Error::MustEndWithAlphanumeric { part, offset } => write!(
+                f,
+                "{} must end with an alphanumeric character (at offset {})",
+                part, offset
+            ),
+            Error::InvalidEscape { offset } => write!(
+                f,
+                "invalid escape sequence in value (at offset {})",
+                offset
+            ),
+            Error::InvalidEncoding(what) => write!(f, "invalid {}", what),
+            Error::InvalidRequirement(reason) => {
+                write!(f, "invalid selector requirement: {}", reason)
+            }
+            Error::DuplicateKey { first, offset } => write!(
+                f,
+                "duplicate key, first given at offset {} (at offset {})",
+                first, offset
+            ),
+            Error::KeyCaseCollision { first, offset } => write!(
+                f,
+                "key differs only in letter case from the key at offset {} (at offset {})",
+                first, offset
+            ),
+            Error::InvalidCommand(message) => write!(f, "{}", message),
+            Error::InvalidArgument { index, error } => write!(f, "argument {}: {}", index, error),
+            // as kubectl aggregates them
+            Error::WouldOverwriteLabels(keys) => {
+                if keys.len() > 1 {
+                    write!(f, "[")?;
+                }
+                for (i, (key, value)) in keys.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(
+                        f,
+                        "'{}' already has a value ({}), and --overwrite is false",
+                        key, value
+                    )?;
+                }
+                if keys.len() > 1 {
+                    write!(f, "]")?;
+                }
+                Ok(())
+            }
+            Error::WouldOverwriteAnnotations(keys) => {
+                write!(
+                    f,
+                    "--overwrite is false but found the following declared annotation(s): "
+                )?;
+                for (i, (key, value)) in keys.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "'{}' already has a value ({})", key, value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// An invalid entry of a list of labels
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct EntryError {
+    /// The position of the entry in the list, counting from 0
+    pub index: usize,
+    /// The bytes of the input the entry spans
+    pub span: core::ops::Range<usize>,
+    /// Why the entry is invalid, with offsets into the whole input
+    pub error: Error,
+}
+
+impl fmt::Display for EntryError {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        write!(f, "entry {}: {}", self.index, self.error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EntryError {}
+
+/// What list parsers do with a key given more than once
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum ConflictPolicy {
+    /// Fail with `Error::DuplicateKey`, or `Error::KeyCaseCollision` for keys
+    /// differing only in letter case
+    Error,
+    /// Keep the first value given for the key
+    FirstWins,
+    /// Keep the last value given for the key, where the key first appeared
+    LastWins,
+}
+
+/// How two keys of a list conflict
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum ConflictKind {
+    /// The same key was given twice
+    Duplicate,
+    /// The keys only differ in letter case, like `app` and `App`, which
+    /// kubernetes tells apart but is most likely a mistake
+    CaseCollision,
+}
+
+/// Two conflicting keys of a list of labels
+///
+/// The spans are bytes of the input for the list parsers, and positions in
+/// the list for `label_map_from_labels_with_policy`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    /// The span of the earlier key
+    pub first: core::ops::Range<usize>,
+    /// The span of the later key
+    pub second: core::ops::Range<usize>,
+}
+
+impl Conflict {
+    pub fn to_error(&self) -> Error {
+        let (first, offset) = (self.first.start, self.second.start);
+        match self.kind {
+            ConflictKind::Duplicate => Error::DuplicateKey { first, offset },
+            ConflictKind::CaseCollision => Error::KeyCaseCollision { first, offset },
+        }
+    }
+}
+
+impl Error {
+    /// The part of the key or label that failed validation, if any
+    pub fn part(&self) -> Option<Part> {
+        match self {
+            Error::EmptyName { .. } | Error::KeyNameTooLong { .. } => Some(Part::Name),
+            Error::EmptyDnsLabel { .. }
+            | Error::PrefixTooLong { .. }
+            | Error::DnsLabelTooLong { .. }
+            | Error::DnsLabelTooShort { .. } => Some(Part::Prefix),
+            Error::ValueTooLong { .. }
+            | Error::InvalidEscape { .. }
+            | Error::InvalidEncoding(_) => Some(Part::Value),
+            Error::InvalidCharacter { part, .. }
+            | Error::MustStartWithAlphanumeric { part, .. }
+            | Error::MustEndWithAlphanumeric { part, .. } => Some(*part),
+            Error::InvalidArgument { error, .. } => error.part(),
+            Error::Syntax { .. }
+            | Error::InvalidRequirement(_)
+            | Error::DuplicateKey { .. }
+            | Error::KeyCaseCollision { .. }
+            | Error::InvalidCommand(_)
+            | Error::WouldOverwriteLabels(_)
+            | Error::WouldOverwriteAnnotations(_) => None,
+        }
+    }
+    /// Where in the input the error was found, if anywhere
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::Syntax { offset, .. }
+            | Error::EmptyName { offset }
+            | Error::EmptyDnsLabel { offset }
+            | Error::KeyNameTooLong { offset, .. }
+            | Error::PrefixTooLong { offset, .. }
+            | Error::DnsLabelTooLong { offset, .. }
+            | Error::DnsLabelTooShort { offset, .. }
+            | Error::ValueTooLong { offset, .. }
+            | Error::InvalidEscape { offset }
+            | Error::InvalidCharacter { offset, .. }
+            | Error::MustStartWithAlphanumeric { offset, .. }
+            | Error::MustEndWithAlphanumeric { offset, .. }
+            | Error::DuplicateKey { offset, .. }
+            | Error::KeyCaseCollision { offset, .. } => Some(*offset),
+            Error::InvalidArgument { error, .. } => error.offset(),
+            Error::InvalidEncoding(_)
+            | Error::InvalidRequirement(_)
+            | Error::InvalidCommand(_)
+            | Error::WouldOverwriteLabels(_)
+            | Error::WouldOverwriteAnnotations(_) => None,
+        }
+    }
+    /// Move the error's offset by `by`, for errors found in part of an input
+    pub(crate) fn shifted(mut self, by: usize) -> Self {
+        match &mut self {
+            Error::Syntax { offset, .. }
+            | Error::EmptyName { offset }
+            | Error::EmptyDnsLabel { offset }
+            | Error::KeyNameTooLong { offset, .. }
+            | Error::PrefixTooLong { offset, .. }
+            | Error::DnsLabelTooLong { offset, .. }
+            | Error::DnsLabelTooShort { offset, .. }
+            | Error::ValueTooLong { offset, .. }
+            | Error::InvalidEscape { offset }
+            | Error::InvalidCharacter { offset, .. }
+            | Error::MustStartWithAlphanumeric { offset, .. }
+            | Error::MustEndWithAlphanumeric { offset, .. } => *offset += by,
+            Error::DuplicateKey { first, offset } | Error::KeyCaseCollision { first, offset } => {
+                *first += by;
+                *offset += by;
+            }
+            // the offset of `InvalidArgument` is into a single argument
+            Error::InvalidEncoding(_)
+            | Error::InvalidRequirement(_)
+            | Error::InvalidArgument { .. }
+            | Error::InvalidCommand(_)
+            | Error::WouldOverwriteLabels(_)
+            | Error::WouldOverwriteAnnotations(_) => (),
+        }
+        self
+    }
+}
 
 #[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord)]
 pub struct KeyPrefix(pub(crate) String);
@@ -93,8 +458,16 @@ pub struct KeyName(pub(crate) String);
 
 string_newtype!(KeyPrefix);
 string_newtype!(KeyName);
-parse_from!(KeyPrefix, parser::label_keyprefix_from_str);
-parse_from!(KeyName, parser::label_keyname_from_str);
+parse_from!(
+    KeyPrefix,
+    parser::label_keyprefix_from_str,
+    parser::label_keyprefix_from_str_with_profile
+);
+parse_from!(
+    KeyName,
+    parser::label_keyname_from_str,
+    parser::label_keyname_from_str_with_profile
+);
 
 /// A kubernetes label/annotation key
 #[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord)]
@@ -105,7 +478,11 @@ pub struct Key {
     name: KeyName,
 }
 
-parse_from!(Key, parser::label_key_from_str);
+parse_from!(
+    Key,
+    parser::label_key_from_str,
+    parser::label_key_from_str_with_profile
+);
 
 impl Key {
     pub fn new(prefix: Option<KeyPrefix>, name: KeyName) -> Self {
@@ -146,6 +523,14 @@ impl Key {
     }
 }
 
+impl Key {
+    /// The bytes of the string form, `prefix/name`
+    pub(crate) fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        let prefix = self.prefix.iter().flat_map(|p| p.bytes().chain(Some(b'/')));
+        prefix.chain(self.name.bytes())
+    }
+}
+
 impl fmt::Display for Key {
     fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
         if let Some(ref prefix) = self.prefix {
@@ -161,7 +546,11 @@ impl fmt::Display for Key {
 pub struct LabelValue(pub(crate) String);
 
 string_newtype!(LabelValue);
-parse_from!(LabelValue, parser::label_value_from_str);
+parse_from!(
+    LabelValue,
+    parser::label_value_from_str,
+    parser::label_value_from_str_with_profile
+);
 
 /// A Label is a key/value following the k8s validation rules
 #[derive(PartialEq, Eq, Debug, Clone, Hash, PartialOrd, Ord)]
@@ -180,7 +569,7 @@ impl Label {
     }
 }
 
-impl std::convert::From<(Key, LabelValue)> for Label {
+impl core::convert::From<(Key, LabelValue)> for Label {
     fn from(input: (Key, LabelValue)) -> Self {
         Label {
             key: input.0,
@@ -206,7 +595,37 @@ impl Annotation {
     }
 }
 
-impl std::convert::From<(Key, String)> for Annotation {
+/// Written as an entry of `annotations_from_str`, quoting the value only
+/// when it can't be read back as is
+impl fmt::Display for Annotation {
+    fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result {
+        write!(f, "{}=", self.key)?;
+        let needs_quotes =
+            self.value.starts_with('"') || self.value.contains(&[',', '\r', '\n'][..]);
+        if !needs_quotes {
+            return write!(f, "{}", self.value);
+        }
+        write!(f, "\"")?;
+        for c in self.value.chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+/// Write annotations as a comma separated list `annotations_from_str` reads
+/// back
+pub fn annotations_to_string(annotations: &[Annotation]) -> String {
+    let entries: Vec<_> = annotations.iter().map(|a| a.to_string()).collect();
+    entries.join(",")
+}
+
+impl core::convert::From<(Key, String)> for Annotation {
     fn from(input: (Key, String)) -> Self {
         Annotation {
             key: input.0,
@@ -216,17 +635,34 @@ impl std::convert::From<(Key, String)> for Annotation {
 }
 
 pub type Labels = Vec<Label>;
+#[cfg(feature = "std")]
 pub type LabelMap = HashMap<Key, LabelValue>;
 pub type Annotations = Vec<Annotation>;
+#[cfg(feature = "std")]
 pub type AnnotationMap = HashMap<Key, String>;
 
+/// Modules for the serde `with` attribute, validating with `Profile::Strict`
 #[cfg(feature = "serde_support")]
-mod serde_extras {
-    use serde::{de::Error, de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
-    use std::fmt;
+pub use self::serde_extras::strict;
 
-    use super::{Key, LabelValue};
-    use crate::parser::{label_key_from_str, label_value_from_str};
+#[cfg(feature = "serde_support")]
+mod serde_extras {
+    use alloc::string::{String, ToString};
+    use core::fmt;
+    #[cfg(feature = "std")]
+    use serde::de::MapAccess;
+    use serde::de::{DeserializeSeed, Error, Visitor};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[cfg(feature = "std")]
+    use super::{AnnotationMap, LabelMap};
+    use super::{Key, KeyName, LabelValue, Profile};
+    use crate::node::{NodeSelector, NodeSelectorTerm};
+    use crate::parser::{
+        label_key_from_str_with_profile, label_keyname_from_str_with_profile,
+        label_value_from_str_with_profile,
+    };
+    use crate::selector::LabelSelectorSpec;
 
     macro_rules! string_newtype_visitor {
         ($ty:ident, $visitor:ident, $parse_func:ident, $expected:expr) => {
@@ -241,21 +677,32 @@ mod serde_extras {
                 where
                     E: Error,
                 {
-                    $parse_func(v).map_err(Error::custom)
+                    $parse_func(v, self.0).map_err(Error::custom)
                 }
 
                 fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
                 where
                     E: Error,
                 {
-                    $parse_func(&v).map_err(Error::custom)
+                    $parse_func(&v, self.0).map_err(Error::custom)
                 }
 
                 fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
                 where
                     E: Error,
                 {
-                    $parse_func(v).map_err(Error::custom)
+                    $parse_func(v, self.0).map_err(Error::custom)
+                }
+            }
+
+            impl<'de> DeserializeSeed<'de> for $visitor {
+                type Value = $ty;
+
+                fn deserialize<D>(self, deserializer: D) -> Result<$ty, D::Error>
+                where
+                    D: Deserializer<'de>,
+                {
+                    deserializer.deserialize_str(self)
                 }
             }
         };
@@ -268,7 +715,7 @@ mod serde_extras {
                 where
                     D: Deserializer<'de>,
                 {
-                    deserializer.deserialize_str($visitor)
+                    deserializer.deserialize_str($visitor(Profile::default()))
                 }
             }
 
@@ -283,22 +730,51 @@ mod serde_extras {
         };
     }
 
-    struct KeyVisitor;
-    struct ValueVisitor;
+    struct KeyVisitor(Profile);
+    struct KeyNameVisitor(Profile);
+    struct ValueVisitor(Profile);
+    #[cfg(feature = "std")]
+    struct LabelMapVisitor(Profile);
 
     string_newtype_visitor!(
         Key,
         KeyVisitor,
-        label_key_from_str,
+        label_key_from_str_with_profile,
         "a valid kubernetes key (with or without prefix)"
     );
+    string_newtype_visitor!(
+        KeyName,
+        KeyNameVisitor,
+        label_keyname_from_str_with_profile,
+        "a valid kubernetes key name"
+    );
     string_newtype_visitor!(
         LabelValue,
         ValueVisitor,
-        label_value_from_str,
+        label_value_from_str_with_profile,
         "a valid kubernetes label value"
     );
 
+    #[cfg(feature = "std")]
+    impl<'de> Visitor<'de> for LabelMapVisitor {
+        type Value = LabelMap;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(formatter, "a map of valid kubernetes labels")
+        }
+
+        fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            let mut res = LabelMap::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some(key) = map.next_key_seed(KeyVisitor(self.0))? {
+                res.insert(key, map.next_value_seed(ValueVisitor(self.0))?);
+            }
+            Ok(res)
+        }
+    }
+
     pub fn serialize_with_display<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
     where
         T: fmt::Display,
@@ -307,18 +783,186 @@ mod serde_extras {
         serializer.collect_str(value)
     }
 
-    //    pub fn deserialize_key<'de, D>(deser: D) -> Result<Key, D::Error>
-    //    where
-    //        D: Deserializer<'de>,
-    //    {
-    //        deser.deserialize_str(KeyVisitor)
-    //    }
-    //
-    //    // module for serde 'with' attr
-    //    pub mod with_key {
-    //        pub use super::{deserialize_key as deserialize, serialize_with_display as serialize};
-    //    }
+    /// Check a key or value deserialized with `Profile::Lenient` again
+    fn check_strict_key<E: Error>(key: &Key) -> Result<(), E> {
+        label_key_from_str_with_profile(&key.to_string(), Profile::Strict)
+            .map(|_| ())
+            .map_err(Error::custom)
+    }
+
+    fn check_strict_value<E: Error>(value: &LabelValue) -> Result<(), E> {
+        label_value_from_str_with_profile(value.as_str(), Profile::Strict)
+            .map(|_| ())
+            .map_err(Error::custom)
+    }
+
+    pub fn serialize_plain<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize_strict_key<'de, D>(deser: D) -> Result<Key, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deser.deserialize_str(KeyVisitor(Profile::Strict))
+    }
+
+    pub fn deserialize_strict_key_name<'de, D>(deser: D) -> Result<KeyName, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deser.deserialize_str(KeyNameVisitor(Profile::Strict))
+    }
+
+    pub fn deserialize_strict_value<'de, D>(deser: D) -> Result<LabelValue, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deser.deserialize_str(ValueVisitor(Profile::Strict))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn deserialize_strict_label_map<'de, D>(deser: D) -> Result<LabelMap, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deser.deserialize_map(LabelMapVisitor(Profile::Strict))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn deserialize_strict_annotation_map<'de, D>(deser: D) -> Result<AnnotationMap, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let map = AnnotationMap::deserialize(deser)?;
+        map.keys().try_for_each(check_strict_key)?;
+        Ok(map)
+    }
+
+    pub fn deserialize_strict_label_selector<'de, D>(
+        deser: D,
+    ) -> Result<LabelSelectorSpec, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let spec = LabelSelectorSpec::deserialize(deser)?;
+        for (key, value) in &spec.match_labels {
+            check_strict_key(key)?;
+            check_strict_value(value)?;
+        }
+        for req in &spec.match_expressions {
+            check_strict_key(&req.key)?;
+            req.values.iter().try_for_each(check_strict_value)?;
+        }
+        Ok(spec)
+    }
+
+    /// `matchFields` hold field paths and node names, which are checked the
+    /// same in every profile
+    fn check_strict_node_selector_term<E: Error>(term: &NodeSelectorTerm) -> Result<(), E> {
+        for req in &term.match_expressions {
+            check_strict_key(&req.key)?;
+            req.values.iter().try_for_each(check_strict_value)?;
+        }
+        Ok(())
+    }
+
+    pub fn deserialize_strict_node_selector_term<'de, D>(
+        deser: D,
+    ) -> Result<NodeSelectorTerm, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let term = NodeSelectorTerm::deserialize(deser)?;
+        check_strict_node_selector_term(&term)?;
+        Ok(term)
+    }
+
+    pub fn deserialize_strict_node_selector<'de, D>(deser: D) -> Result<NodeSelector, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let selector = NodeSelector::deserialize(deser)?;
+        selector
+            .node_selector_terms
+            .iter()
+            .try_for_each(check_strict_node_selector_term)?;
+        Ok(selector)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn serialize_label_map<S>(map: &LabelMap, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(map)
+    }
+
+    pub mod strict {
+        // module for serde 'with' attr
+        pub mod key {
+            pub use super::super::{
+                deserialize_strict_key as deserialize, serialize_with_display as serialize,
+            };
+        }
+
+        // module for serde 'with' attr
+        pub mod key_name {
+            pub use super::super::{
+                deserialize_strict_key_name as deserialize, serialize_with_display as serialize,
+            };
+        }
+
+        // module for serde 'with' attr
+        pub mod value {
+            pub use super::super::{
+                deserialize_strict_value as deserialize, serialize_with_display as serialize,
+            };
+        }
+
+        // module for serde 'with' attr
+        #[cfg(feature = "std")]
+        pub mod label_map {
+            pub use super::super::{
+                deserialize_strict_label_map as deserialize, serialize_label_map as serialize,
+            };
+        }
+
+        // module for serde 'with' attr
+        #[cfg(feature = "std")]
+        pub mod annotation_map {
+            pub use super::super::{
+                deserialize_strict_annotation_map as deserialize, serialize_plain as serialize,
+            };
+        }
+
+        // module for serde 'with' attr
+        pub mod label_selector {
+            pub use super::super::{
+                deserialize_strict_label_selector as deserialize, serialize_plain as serialize,
+            };
+        }
+
+        // module for serde 'with' attr
+        pub mod node_selector {
+            pub use super::super::{
+                deserialize_strict_node_selector as deserialize, serialize_plain as serialize,
+            };
+        }
+
+        // module for serde 'with' attr
+        pub mod node_selector_term {
+            pub use super::super::{
+                deserialize_strict_node_selector_term as deserialize, serialize_plain as serialize,
+            };
+        }
+    }
 
     string_newtype_instances!(Key, KeyVisitor);
+    string_newtype_instances!(KeyName, KeyNameVisitor);
     string_newtype_instances!(LabelValue, ValueVisitor);
 }
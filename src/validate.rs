@@ -0,0 +1,150 @@
+//! Explains why a key, prefix, name or value was rejected
+//!
+//! These checks mirror `labels.pest` and run once the grammar has failed, to
+//! turn the failure into a typed `Error`. The grammar accepts the prefixes and
+//! values of every `Profile`, so `check_prefix` and `check_value` also apply
+//! the profile specific rules to whatever the grammar accepted.
+
+use crate::types::{Error, Part, Profile};
+
+pub(crate) const MAX_NAME_LEN: usize = 63;
+pub(crate) const MAX_VALUE_LEN: usize = 63;
+pub(crate) const MAX_PREFIX_LEN: usize = 255;
+pub(crate) const MAX_STRICT_PREFIX_LEN: usize = 253;
+pub(crate) const MIN_DNS_LABEL_LEN: usize = 2;
+pub(crate) const MAX_DNS_LABEL_LEN: usize = 63;
+
+fn is_alphanumeric(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+}
+
+fn is_name_char(c: char) -> bool {
+    is_alphanumeric(c) || c == '-' || c == '_' || c == '.'
+}
+
+fn is_dns_label_char(c: char) -> bool {
+    is_alphanumeric(c) || c == '-'
+}
+
+fn is_strict_dns_label_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'
+}
+
+/// The checks shared by key names and values, `offset` is where `input`
+/// starts in the string handed to the parser
+fn check_part(
+    input: &str,
+    offset: usize,
+    part: Part,
+    allowed: fn(char) -> bool,
+) -> Result<(), Error> {
+    if let Some((i, ch)) = input.char_indices().find(|&(_, c)| !allowed(c)) {
+        return Err(Error::InvalidCharacter {
+            part,
+            ch,
+            offset: offset + i,
+        });
+    }
+    if !input.starts_with(is_alphanumeric) {
+        return Err(Error::MustStartWithAlphanumeric { part, offset });
+    }
+    if !input.ends_with(is_alphanumeric) {
+        return Err(Error::MustEndWithAlphanumeric {
+            part,
+            offset: offset + input.len() - 1,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_name(input: &str, offset: usize) -> Result<(), Error> {
+    if input.is_empty() {
+        return Err(Error::EmptyName { offset });
+    }
+    check_part(input, offset, Part::Name, is_name_char)?;
+    if input.len() > MAX_NAME_LEN {
+        return Err(Error::KeyNameTooLong {
+            len: input.len(),
+            offset,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_value(input: &str, offset: usize, profile: Profile) -> Result<(), Error> {
+    if input.is_empty() {
+        return Ok(());
+    }
+    check_part(input, offset, Part::Value, is_name_char)?;
+    if profile == Profile::Strict && input.len() > MAX_VALUE_LEN {
+        return Err(Error::ValueTooLong {
+            len: input.len(),
+            offset,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_prefix(input: &str, offset: usize, profile: Profile) -> Result<(), Error> {
+    let (allowed, max_len): (fn(char) -> bool, usize) = match profile {
+        Profile::Strict => (is_strict_dns_label_char, MAX_STRICT_PREFIX_LEN),
+        Profile::Lenient => (is_dns_label_char, MAX_PREFIX_LEN),
+    };
+    let mut label_offset = offset;
+    for label in input.split('.') {
+        if label.is_empty() {
+            return Err(Error::EmptyDnsLabel {
+                offset: label_offset,
+            });
+        }
+        check_part(label, label_offset, Part::Prefix, allowed)?;
+        // kubernetes itself only limits the length of the whole subdomain
+        if profile == Profile::Lenient && label.len() > MAX_DNS_LABEL_LEN {
+            return Err(Error::DnsLabelTooLong {
+                len: label.len(),
+                offset: label_offset,
+            });
+        }
+        if profile == Profile::Lenient && label.len() < MIN_DNS_LABEL_LEN {
+            return Err(Error::DnsLabelTooShort {
+                len: label.len(),
+                offset: label_offset,
+            });
+        }
+        label_offset += label.len() + 1;
+    }
+    if input.len() > max_len {
+        return Err(Error::PrefixTooLong {
+            len: input.len(),
+            max: max_len,
+            offset,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_key(input: &str, offset: usize, profile: Profile) -> Result<(), Error> {
+    match input.find('/') {
+        Some(i) => {
+            check_prefix(&input[..i], offset, profile)?;
+            check_name(&input[i + 1..], offset + i + 1)
+        }
+        None => check_name(input, offset),
+    }
+}
+
+/// Check a `key<sep>value` entry, or just its key when `sep` is missing
+pub(crate) fn check_entry(
+    input: &str,
+    offset: usize,
+    sep: &[char],
+    profile: Profile,
+) -> Result<(), Error> {
+    match input.find(sep) {
+        Some(i) => {
+            check_key(&input[..i], offset, profile)?;
+            check_value(&input[i + 1..], offset + i + 1, profile)
+        }
+        None => check_key(input, offset, profile),
+    }
+}